@@ -1,445 +1,3920 @@
-use std::process::{Command as ProcessCommand, ExitStatus};
-
-use failure::*;
-
-trait Executor {
-    type ExitStatus: Success;
-
-    fn execute(&mut self, command: &str, args: &[&str]) -> Result<Self::ExitStatus, Error>;
-}
-
-trait Success {
-    fn success(&self) -> bool;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::error::Error;
+
+use crate::builtins::{Builtin, Context, Status};
+use crate::env::{Env, Unwind};
+use crate::executor::*;
+
+/// Where a node came from in the script: its bytes, and the line and column it starts on, both
+/// counting from 1. Nodes leave their spans out of equality, so they compare equal wherever they
+/// were parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-struct StdExecutor;
-
-impl Executor for StdExecutor {
-    type ExitStatus = ExitStatus;
+impl Span {
+    /// Whether the node was parsed from a script, rather than built some other way.
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
 
-    fn execute(&mut self, command: &str, args: &[&str]) -> Result<Self::ExitStatus, Error> {
-        let exit = ProcessCommand::new(command).args(args).spawn()?.wait()?;
-        Ok(exit)
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        if !self.is_known() {
+            return other;
+        }
+        if !other.is_known() {
+            return self;
+        }
+        let (first, last) = if other.start < self.start {
+            (other, self)
+        } else {
+            (self, other)
+        };
+        Span {
+            end: first.end.max(last.end),
+            ..first
+        }
     }
 }
 
-impl Success for ExitStatus {
-    fn success(&self) -> bool {
-        self.success()
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// Implements equality for a node from all of its fields but `span`.
+macro_rules! eq_without_span {
+    ($node:ident { $($field:ident),+ }) => {
+        impl PartialEq for $node {
+            fn eq(&self, other: &$node) -> bool {
+                $(self.$field == other.$field)&&+
+            }
+        }
+
+        impl Eq for $node {}
+    };
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
 pub enum AST {
     Comment(String),
     Command(Command),
     If(Conditional),
     Block(Block),
+    Subshell(Subshell),
     While(While),
+    Let(Assignment),
+    Assign(Assignment),
+    For(For),
+    Pipeline(Pipeline),
+    Predicate(Predicate),
+    Function(Rc<Function>),
+    Return(Option<Word>, Span),
+    /// `@strict`, `@continue` or `@warn` on a line of its own, for the rest of the block.
+    Pragma(FailurePolicy),
+    Try(Try),
+    /// `break`, with the label of the loop to leave if it names one.
+    Break(Option<String>, Span),
+    /// `continue`, with the label of the loop to go on with if it names one.
+    Continue(Option<String>, Span),
+}
+
+impl PartialEq for AST {
+    fn eq(&self, other: &AST) -> bool {
+        match (self, other) {
+            (AST::Comment(a), AST::Comment(b)) => a == b,
+            (AST::Command(a), AST::Command(b)) => a == b,
+            (AST::If(a), AST::If(b)) => a == b,
+            (AST::Block(a), AST::Block(b)) => a == b,
+            (AST::Subshell(a), AST::Subshell(b)) => a == b,
+            (AST::While(a), AST::While(b)) => a == b,
+            (AST::Let(a), AST::Let(b)) | (AST::Assign(a), AST::Assign(b)) => a == b,
+            (AST::For(a), AST::For(b)) => a == b,
+            (AST::Pipeline(a), AST::Pipeline(b)) => a == b,
+            (AST::Predicate(a), AST::Predicate(b)) => a == b,
+            (AST::Function(a), AST::Function(b)) => a == b,
+            (AST::Return(a, _), AST::Return(b, _)) => a == b,
+            (AST::Pragma(a), AST::Pragma(b)) => a == b,
+            (AST::Try(a), AST::Try(b)) => a == b,
+            (AST::Break(a, _), AST::Break(b, _)) | (AST::Continue(a, _), AST::Continue(b, _)) => {
+                a == b
+            }
+            _ => false,
+        }
+    }
 }
 
+impl Eq for AST {}
+
 impl AST {
-    pub fn execute(&self) -> Result<Option<ExitStatus>, Error> {
-        self.execute_with(&mut StdExecutor)
+    /// Where the statement is in the script. Comments and pragmas don't keep one.
+    pub fn span(&self) -> Span {
+        match self {
+            AST::Comment(_) | AST::Pragma(_) => Span::default(),
+            AST::Return(_, span) | AST::Break(_, span) | AST::Continue(_, span) => *span,
+            AST::Command(c) => c.span,
+            AST::If(c) => c.span,
+            AST::Block(b) => b.span,
+            AST::Subshell(s) => s.span,
+            AST::While(w) => w.span,
+            AST::For(f) => f.span,
+            AST::Pipeline(p) => p.span(),
+            AST::Predicate(p) => p.span(),
+            AST::Function(f) => f.span,
+            AST::Let(a) | AST::Assign(a) => a.span,
+            AST::Try(t) => t.span,
+        }
+    }
+
+    pub fn execute_with<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        self.run(executor, env).map_err(|e| e.at(self.span()))
+    }
+
+    /// Runs the statement as the body of another, whose scope already stands for a block body.
+    fn execute_body<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        match self {
+            AST::Block(b) => b.execute(executor, env).map_err(|e| e.at(b.span)),
+            _ => self.execute_with(executor, env),
+        }
     }
 
-    fn execute_with<E: Executor>(&self, executor: &mut E) -> Result<Option<E::ExitStatus>, Error> {
+    fn run<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
         match self {
             AST::Comment(_) => Ok(None),
-            AST::Command(c) => c.execute(executor).map(Some),
-            AST::If(c) => c.execute(executor),
-            AST::Block(b) => b.execute(executor),
-            AST::While(w) => w.execute(executor),
+            AST::Command(c) => c.execute(executor, env).map(Some),
+            AST::If(c) => c.execute(executor, env),
+            AST::Block(b) => nested(executor, format_args!("block"), |e| b.execute(e, env)),
+            AST::Subshell(s) => s.execute(executor, env).map(Some),
+            AST::While(w) => w.execute(executor, env),
+            AST::For(f) => f.execute(executor, env),
+            AST::Pipeline(p) => p.execute(executor, env).map(Some),
+            AST::Predicate(p) => p.execute(executor, env).map(Some),
+            AST::Function(f) => {
+                env.define_function(Rc::clone(f));
+                Ok(None)
+            }
+            AST::Return(value, _) => {
+                if !env.in_call() {
+                    return Err(Error::runtime("return outside of a function"));
+                }
+                let status = match value {
+                    Some(value) => {
+                        let value = value.expand_string(executor, env)?;
+                        let code = value.parse().map_err(|_| {
+                            Error::runtime(format!("return status is not an integer: {}", value))
+                        })?;
+                        Some(E::ExitStatus::from_code(code))
+                    }
+                    None => None,
+                };
+                env.unwind(Unwind::Return);
+                Ok(status)
+            }
+            AST::Pragma(policy) => {
+                env.set_failure_policy(*policy);
+                Ok(None)
+            }
+            AST::Try(t) => t.execute(executor, env),
+            AST::Break(label, _) => {
+                env.ensure_in_loop("break", label.as_deref())?;
+                env.unwind(Unwind::Break(label.clone()));
+                Ok(None)
+            }
+            AST::Continue(label, _) => {
+                env.ensure_in_loop("continue", label.as_deref())?;
+                env.unwind(Unwind::Continue(label.clone()));
+                Ok(None)
+            }
+            AST::Let(a) => {
+                let value = a.value.expand_string(executor, env)?;
+                env.define(&a.name, value);
+                Ok(None)
+            }
+            AST::Assign(a) => {
+                let value = a.value.expand_string(executor, env)?;
+                env.assign(&a.name, value)?;
+                Ok(None)
+            }
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// A piece of a shell word, either taken verbatim or looked up when the word is expanded.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum WordPart {
+    /// Bytes from the script, which need not be UTF-8.
+    Literal(OsString),
+    Variable(String),
+    /// `$1`, the script's arguments counting from one, or `$0`, the script itself.
+    Argument(usize),
+    /// `$@`, every argument to the script.
+    Arguments,
+    /// `$#`, how many arguments the script was given.
+    ArgumentCount,
+    /// `$(command)`, replaced by the command's stdout without trailing newlines.
+    Command(Box<Command>),
+}
+
+/// A single argument as written in the script, before variables are expanded.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Word(Vec<WordPart>);
+
+impl Word {
+    /// Builds a word, merging adjacent literals so equal words compare equal however they were
+    /// parsed.
+    pub fn new(parts: Vec<WordPart>) -> Word {
+        let mut merged: Vec<WordPart> = Vec::with_capacity(parts.len());
+        for part in parts {
+            match (merged.last_mut(), part) {
+                (_, WordPart::Literal(ref s)) if s.is_empty() => {}
+                (Some(WordPart::Literal(last)), WordPart::Literal(s)) => last.push(&s),
+                (_, part) => merged.push(part),
+            }
+        }
+        Word(merged)
+    }
+
+    /// The word's text, if it has no expansions.
+    pub fn as_literal(&self) -> Option<String> {
+        match &self.0[..] {
+            [] => Some(String::new()),
+            [WordPart::Literal(s)] => s.to_str().map(String::from),
+            _ => None,
+        }
+    }
+
+    fn expand<E: Executor>(&self, executor: &mut E, env: &mut Env) -> Result<OsString, Error> {
+        let mut result = OsString::new();
+        for part in &self.0 {
+            match part {
+                WordPart::Literal(s) => result.push(s),
+                WordPart::Variable(name) => result.push(env.get(name)?),
+                WordPart::Argument(index) => result.push(env.argument(*index)?),
+                WordPart::Arguments => result.push(join(env.arguments())),
+                WordPart::ArgumentCount => result.push(env.arguments().len().to_string()),
+                WordPart::Command(command) => {
+                    let (status, output) = command.capture(executor, env)?;
+                    if !status.success() {
+                        return Err(Error::runtime(format!(
+                            "command substitution failed: {}",
+                            command.command
+                        )));
+                    }
+                    result.push(output.trim_end_matches('\n'));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Expands the word into the arguments it stands for. A lone `$@` is one argument per script
+    /// argument, while every other word is exactly one.
+    fn expand_fields<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Vec<OsString>, Error> {
+        match &self.0[..] {
+            [WordPart::Arguments] => Ok(env.arguments().to_vec()),
+            _ => Ok(vec![self.expand(executor, env)?]),
+        }
+    }
+
+    /// Expands the word for use where only text makes sense, like a variable's value.
+    fn expand_string<E: Executor>(&self, executor: &mut E, env: &mut Env) -> Result<String, Error> {
+        into_utf8(self.expand(executor, env)?)
+    }
+}
+
+impl<'a> From<&'a str> for Word {
+    fn from(s: &'a str) -> Word {
+        Word::new(vec![WordPart::Literal(s.into())])
+    }
+}
+
+impl From<String> for Word {
+    fn from(s: String) -> Word {
+        Word::new(vec![WordPart::Literal(s.into())])
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Command {
     command: String,
-    args: Vec<String>,
+    args: Vec<Word>,
+    redirects: Vec<Redirect>,
+    span: Span,
 }
 
+eq_without_span!(Command {
+    command,
+    args,
+    redirects
+});
+
 impl Command {
+    #[cfg(test)]
     pub fn new<S>(s: S, args: Vec<S>) -> Command
     where
         S: ToString,
     {
+        Command::with_words(s, args.into_iter().map(|s| s.to_string().into()).collect())
+    }
+
+    pub fn with_words<S: ToString>(s: S, args: Vec<Word>) -> Command {
         Command {
             command: s.to_string(),
-            args: args.into_iter().map(|s| s.to_string()).collect(),
+            args,
+            redirects: Vec::new(),
+            span: Span::default(),
         }
     }
 
+    pub fn redirect(mut self, redirects: Vec<Redirect>) -> Command {
+        self.redirects.extend(redirects);
+        self
+    }
+
+    pub fn at(mut self, span: Span) -> Command {
+        self.span = span;
+        self
+    }
+
+    #[cfg(test)]
     fn no_args<S: ToString>(s: S) -> Command {
         Self::new(s, vec![])
     }
 }
 
 impl Command {
-    fn execute<E: Executor>(&self, executor: &mut E) -> Result<E::ExitStatus, Error> {
-        let exit = executor.execute(
-            &self.command,
-            &self.args.iter().map(AsRef::as_ref).collect::<Vec<_>>(),
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<E::ExitStatus, Error> {
+        self.run(executor, env, E::execute)
+            .map_err(|e| e.at(self.span))
+    }
+
+    /// Runs the command for a condition, which only matters to the executor.
+    fn test<E: Executor>(&self, executor: &mut E, env: &mut Env) -> Result<E::ExitStatus, Error> {
+        self.run(executor, env, E::condition)
+            .map_err(|e| e.at(self.span))
+    }
+
+    fn run<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+        spawn: fn(&mut E, &Invocation) -> Result<E::ExitStatus, Error>,
+    ) -> Result<E::ExitStatus, Error> {
+        let invocation = self.invocation(executor, env)?;
+        self.run_expanded(&invocation, Streams::default(), executor, env, spawn)
+    }
+
+    /// Runs the command with its words already expanded into `invocation`. A function or builtin
+    /// uses `inherited` in place of this process's own streams.
+    fn run_expanded<E: Executor>(
+        &self,
+        invocation: &Invocation,
+        inherited: Streams,
+        executor: &mut E,
+        env: &mut Env,
+        spawn: fn(&mut E, &Invocation) -> Result<E::ExitStatus, Error>,
+    ) -> Result<E::ExitStatus, Error> {
+        if let Some(function) = env.function(&self.command) {
+            let status = call(&function, invocation, inherited, executor, env);
+            // The body's statements each cleared it, so the call is recorded once it returns.
+            env.set_last_command(invocation.to_string());
+            return status;
+        }
+        env.set_last_command(invocation.to_string());
+        match env.builtin(&self.command) {
+            Some(builtin) => {
+                let state_only = env.is_state_builtin(&self.command);
+                run_builtin(&*builtin, state_only, invocation, inherited, executor, env)
+            }
+            None => spawn(executor, invocation),
+        }
+    }
+
+    fn capture<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<(E::ExitStatus, String), Error> {
+        let invocation = self.invocation(executor, env)?;
+        if !self.is_internal(env) {
+            return executor.capture(&invocation);
+        }
+        let (status, output) = collect_output(|stdout| {
+            let inherited = Streams {
+                stdout: Some(stdout),
+                ..Streams::default()
+            };
+            self.run_expanded(&invocation, inherited, executor, env, E::execute)
+        })?;
+        let output = String::from_utf8(output).map_err(|_| {
+            Error::runtime(format!(
+                "{} produced output that is not UTF-8",
+                self.command
+            ))
+        })?;
+        Ok((status, output))
+    }
+
+    /// Whether the shell runs the command itself rather than starting a program.
+    fn is_internal(&self, env: &Env) -> bool {
+        env.function(&self.command).is_some() || env.builtin(&self.command).is_some()
+    }
+
+    /// The program to run. `~` stands for the home directory, and a relative path containing a
+    /// `/` is taken from the current directory instead of being searched for.
+    fn program(&self, env: &Env) -> Result<Cow<'_, OsStr>, Error> {
+        let name = match self.command.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                Cow::Owned(format!("{}{}", env.get("HOME")?, rest).into())
+            }
+            _ => Cow::Borrowed(OsStr::new(&self.command)),
+        };
+        if name.as_bytes().contains(&b'/') && !Path::new(&name).is_absolute() {
+            return Ok(Cow::Owned(env.current_dir().join(&name).into_os_string()));
+        }
+        Ok(name)
+    }
+
+    fn invocation<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Invocation<'_>, Error> {
+        Ok(Invocation {
+            command: self.program(env)?,
+            args: self
+                .args
+                .iter()
+                .map(|a| a.expand_fields(executor, env))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+            redirects: self
+                .redirects
+                .iter()
+                .map(|r| r.expand(executor, env))
+                .collect::<Result<_, _>>()?,
+            dir: env.current_dir().to_owned(),
+            env: env.exports().clone(),
+        })
+    }
+}
+
+/// Runs the body of `function` with the arguments in `invocation`, as a command the executor
+/// runs internally. The streams it decides on stand in for this process's own while the body
+/// runs.
+fn call<E: Executor>(
+    function: &Function,
+    invocation: &Invocation,
+    inherited: Streams,
+    executor: &mut E,
+    env: &mut Env,
+) -> Result<E::ExitStatus, Error> {
+    if invocation.args.len() != function.params.len() {
+        return Err(Error::runtime(format!(
+            "{} expects {} arguments, got {}",
+            function.name,
+            function.params.len(),
+            invocation.args.len()
+        )));
+    }
+    let args = invocation
+        .args
+        .iter()
+        .cloned()
+        .map(into_utf8)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let run = |executor: &mut E, env: &mut Env| {
+        env.enter_call()?;
+        env.push_scope();
+        for (param, arg) in function.params.iter().zip(args.iter()) {
+            env.define(param, arg.clone());
+        }
+        // The body keeps to its own annotation, not whatever policy the caller is under.
+        let outer = env.failure_policy();
+        env.set_failure_policy(FailurePolicy::Strict);
+        let result = function.body.execute(executor, env);
+        env.set_failure_policy(outer);
+        env.pop_scope();
+        env.leave_call();
+        env.catch(Unwind::Return);
+
+        Ok(result?.unwrap_or_else(|| E::ExitStatus::from_code(0)))
+    };
+    executor.internal(invocation, inherited, &mut |executor, streams| {
+        let _installed = streams.map(Streams::install).transpose()?;
+        run(executor, env)
+    })
+}
+
+/// Runs `builtin` in this process as `invocation`, with the streams the executor hands it.
+/// Builtins never read stdin, so only output redirections matter.
+fn run_builtin<E: Executor>(
+    builtin: &Builtin,
+    state_only: bool,
+    invocation: &Invocation,
+    inherited: Streams,
+    executor: &mut E,
+    env: &mut Env,
+) -> Result<E::ExitStatus, Error> {
+    let args = invocation
+        .args
+        .iter()
+        .cloned()
+        .map(into_utf8)
+        .collect::<Result<Vec<_>, _>>()?;
+    executor.internal(invocation, inherited, &mut |executor, streams| {
+        let (mut stdout, mut stderr): (Box<dyn Write>, Box<dyn Write>) = match streams {
+            Some(streams) => (
+                or_inherited(streams.stdout, io::stdout()),
+                or_inherited(streams.stderr, io::stderr()),
+            ),
+            // Nothing really runs, but later commands depend on what a builtin like `cd` changes.
+            None if state_only => (Box::new(io::sink()), Box::new(io::sink())),
+            None => return Ok(E::ExitStatus::from_code(0)),
+        };
+
+        let mut run = |block: &Block, env: &mut Env| {
+            let status = block.run(executor, env)?;
+            Ok(Status::from_code(status.map_or(0, |s| s.exit_code())))
+        };
+        let status = builtin(
+            &args,
+            &mut Context::new(env, &mut *stdout, &mut *stderr, &mut run),
         )?;
-        Ok(exit)
+        stdout.flush()?;
+        Ok(E::ExitStatus::from_code(status.exit_code()))
+    })
+}
+
+/// Where a builtin writes to a stream: the file it's redirected to, or else `inherited`.
+fn or_inherited<W: Write + 'static>(file: Option<File>, inherited: W) -> Box<dyn Write> {
+    match file {
+        Some(file) => Box::new(file),
+        None => Box::new(inherited),
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A stream that can be sent to a file.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Fd {
+    Stdout,
+    Stderr,
+}
+
+/// Where one of a command's standard streams goes, applied in the order written.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Redirect<P = Word> {
+    /// `< path`
+    Read(P),
+    /// `> path`, `>> path`, `2> path` and `2>> path`
+    Write { fd: Fd, path: P, append: bool },
+    /// `2>&1`
+    StderrToStdout,
+}
+
+impl Redirect {
+    fn expand<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Redirect<OsString>, Error> {
+        Ok(match self {
+            Redirect::Read(path) => Redirect::Read(path.expand(executor, env)?),
+            Redirect::Write { fd, path, append } => Redirect::Write {
+                fd: *fd,
+                path: path.expand(executor, env)?,
+                append: *append,
+            },
+            Redirect::StderrToStdout => Redirect::StderrToStdout,
+        })
+    }
+}
+
+/// Which stage of a pipeline decides the status of the whole pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PipeStatus {
+    /// The status of the last stage, like a plain POSIX shell.
+    #[default]
+    Last,
+    /// The status of the last stage that failed, like bash's `pipefail`.
+    AnyFailure,
+}
+
+impl PipeStatus {
+    fn select<S: Success>(self, mut statuses: Vec<S>) -> S {
+        if self == PipeStatus::AnyFailure {
+            if let Some(i) = statuses.iter().rposition(|s| !s.success()) {
+                return statuses.swap_remove(i);
+            }
+        }
+        statuses
+            .pop()
+            .expect("Pipelines always have at least one stage")
+    }
+}
+
+/// What a block does when one of its statements fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Stop the block there and fail with that status, like `set -e`.
+    #[default]
+    Strict,
+    /// Carry on, so the block's status is that of its last statement.
+    Continue,
+    /// Carry on, but report the failure on stderr.
+    Warn,
+}
+
+impl FailurePolicy {
+    /// Whether statements after one that finished with `status` at `span` should still run.
+    fn keep_going<S: Success>(self, status: &S, span: Span, env: &Env) -> bool {
+        if status.success() {
+            return true;
+        }
+        match self {
+            FailurePolicy::Strict => false,
+            FailurePolicy::Continue => true,
+            FailurePolicy::Warn => {
+                eprintln!("{}", warning(status, span, env));
+                true
+            }
+        }
+    }
+}
+
+/// The warning for a statement that failed with `status` under `@warn`, located the way an
+/// error there would be.
+fn warning<S: Success>(status: &S, span: Span, env: &Env) -> Error {
+    let warning = Error::runtime(format!(
+        "warning: {} failed with status {}",
+        env.last_command().unwrap_or("statement"),
+        status.exit_code()
+    ))
+    .at(span);
+    match env.file() {
+        Some(file) => warning.in_file(file),
+        None => warning,
+    }
+}
+
+/// Commands connected with `|`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Pipeline(pub Vec<Command>);
+
+impl Pipeline {
+    fn span(&self) -> Span {
+        let first = self.0.first().map(|c| c.span).unwrap_or_default();
+        let last = self.0.last().map(|c| c.span).unwrap_or_default();
+        first.to(last)
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<E::ExitStatus, Error> {
+        let stages = self
+            .0
+            .iter()
+            .map(|c| c.invocation(executor, env).map_err(|e| e.at(c.span)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let line: Vec<_> = stages.iter().map(ToString::to_string).collect();
+        env.set_last_command(line.join(" | "));
+        let statuses = if self.0.iter().any(|c| c.is_internal(env)) {
+            match executor.fork_pipeline(&stages)? {
+                Forked::Parent(statuses) => statuses,
+                Forked::Child(i) => {
+                    let command = &self.0[i];
+                    let status = command
+                        .run_expanded(&stages[i], Streams::default(), executor, env, E::execute)
+                        .map_err(|e| e.at(command.span));
+                    exit_child(status)
+                }
+            }
+        } else {
+            executor.pipeline(&stages)?
+        };
+        if statuses.len() != stages.len() {
+            return Err(Error::Internal(format!(
+                "pipeline of {} stages produced {} statuses",
+                stages.len(),
+                statuses.len()
+            )));
+        }
+        Ok(env.pipe_status().select(statuses))
+    }
+}
+
+/// Commands combined with `!`, `&&` and `||`, evaluated left to right with short-circuiting.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Predicate {
+    Command(Command),
+    /// Decided by the pipeline's status, the same as it would have as a statement of its own.
+    Pipeline(Pipeline),
+    /// Succeeds if the inner predicate fails, and vice versa.
+    Not(Box<Predicate>),
+    /// Only evaluates the right side if the left side succeeds.
+    And(Box<Predicate>, Box<Predicate>),
+    /// Only evaluates the right side if the left side fails.
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+impl Predicate {
+    fn span(&self) -> Span {
+        match self {
+            Predicate::Command(c) => c.span,
+            Predicate::Pipeline(p) => p.span(),
+            Predicate::Not(p) => p.span(),
+            Predicate::And(a, b) | Predicate::Or(a, b) => a.span().to(b.span()),
+        }
+    }
+}
+
+impl Predicate {
+    pub fn not(predicate: Predicate) -> Predicate {
+        Predicate::Not(Box::new(predicate))
+    }
+
+    pub fn and(left: Predicate, right: Predicate) -> Predicate {
+        Predicate::And(Box::new(left), Box::new(right))
+    }
+
+    pub fn or(left: Predicate, right: Predicate) -> Predicate {
+        Predicate::Or(Box::new(left), Box::new(right))
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<E::ExitStatus, Error> {
+        match self {
+            Predicate::Command(c) => c.test(executor, env),
+            Predicate::Pipeline(p) => p.execute(executor, env),
+            Predicate::Not(p) => {
+                let status = p.execute(executor, env)?;
+                Ok(E::ExitStatus::from_code(if status.success() {
+                    1
+                } else {
+                    0
+                }))
+            }
+            Predicate::And(left, right) => {
+                let status = left.execute(executor, env)?;
+                if status.success() && !env.is_unwinding() {
+                    right.execute(executor, env)
+                } else {
+                    Ok(status)
+                }
+            }
+            Predicate::Or(left, right) => {
+                let status = left.execute(executor, env)?;
+                if status.success() || env.is_unwinding() {
+                    Ok(status)
+                } else {
+                    right.execute(executor, env)
+                }
+            }
+        }
+    }
+}
+
+impl From<Command> for Predicate {
+    fn from(command: Command) -> Predicate {
+        Predicate::Command(command)
+    }
+}
+
+/// A `let` binding or a reassignment of an existing variable.
+#[derive(Debug)]
+pub struct Assignment {
+    name: String,
+    value: Word,
+    span: Span,
+}
+
+eq_without_span!(Assignment { name, value });
+
+impl Assignment {
+    pub fn new<S: ToString>(name: S, value: Word) -> Assignment {
+        Assignment {
+            name: name.to_string(),
+            value,
+            span: Span::default(),
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Assignment {
+        self.span = span;
+        self
+    }
+}
+
+#[derive(Debug)]
 pub struct Conditional {
-    predicate: Command,
+    predicate: Predicate,
     if_block: Box<AST>,
     else_block: Option<Box<AST>>,
+    span: Span,
 }
 
+eq_without_span!(Conditional {
+    predicate,
+    if_block,
+    else_block
+});
+
 impl Conditional {
-    pub fn new(predicate: Command, if_block: AST, else_block: Option<AST>) -> Conditional {
+    pub fn new<P: Into<Predicate>>(
+        predicate: P,
+        if_block: AST,
+        else_block: Option<AST>,
+    ) -> Conditional {
         Conditional {
-            predicate,
+            predicate: predicate.into(),
             if_block: Box::new(if_block),
             else_block: else_block.map(Box::new),
+            span: Span::default(),
         }
     }
 
-    fn execute<E: Executor>(&self, executor: &mut E) -> Result<Option<E::ExitStatus>, Error> {
-        if self.predicate.execute(executor)?.success() {
-            self.if_block.execute_with(executor)
+    pub fn at(mut self, span: Span) -> Conditional {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let status = nested(executor, format_args!("if"), |e| {
+            self.predicate.execute(e, env)
+        })?;
+        if env.is_unwinding() {
+            Ok(Some(status))
+        } else if status.success() {
+            nested(executor, format_args!("then"), |e| {
+                self.if_block.execute_body(e, env)
+            })
         } else {
             match &self.else_block {
                 None => Ok(None),
-                Some(b) => b.execute_with(executor),
+                Some(b) => nested(executor, format_args!("else"), |e| b.execute_body(e, env)),
             }
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Block(pub Vec<AST>);
+#[derive(Debug)]
+pub struct Block {
+    pub statements: Vec<AST>,
+    policy: Option<FailurePolicy>,
+    span: Span,
+}
+
+eq_without_span!(Block { statements, policy });
 
 impl Block {
-    fn execute<E: Executor>(&self, executor: &mut E) -> Result<Option<E::ExitStatus>, Error> {
-        let iter = self.0.iter().map(|ast| ast.execute_with(executor));
-        consume_until_exit::<E, _>(iter)
+    pub fn new(statements: Vec<AST>) -> Block {
+        Block {
+            statements,
+            policy: None,
+            span: Span::default(),
+        }
+    }
+
+    /// Runs the block with `policy` rather than the one in force where it appears.
+    pub fn with_policy(mut self, policy: FailurePolicy) -> Block {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn at(mut self, span: Span) -> Block {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        env.push_scope();
+        let result = self.run(executor, env);
+        env.pop_scope();
+        result
+    }
+
+    /// Runs the statements in the current scope, stopping after the first one that fails unless
+    /// the failure policy says otherwise. A pragma inside lasts until the block ends.
+    fn run<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let outer = env.failure_policy();
+        if let Some(policy) = self.policy {
+            env.set_failure_policy(policy);
+        }
+
+        let state = RefCell::new((executor, env));
+        let iter = self
+            .statements
+            .iter()
+            .take_while(|_| {
+                let env = &mut state.borrow_mut().1;
+                env.check_interrupt();
+                !env.is_unwinding()
+            })
+            .map(|ast| {
+                let (executor, env) = &mut *state.borrow_mut();
+                env.clear_last_command();
+                (ast.span(), ast.execute_with(&mut **executor, env))
+            });
+        let result = consume_until_exit::<E, _>(iter, |status, span| {
+            let env = &state.borrow().1;
+            env.failure_policy().keep_going(status, span, env)
+        });
+
+        state.into_inner().1.set_failure_policy(outer);
+        result
+    }
+
+    /// Runs the statements as a whole script, returning the code the process should exit with:
+    /// the status of the statement that stopped it or called `exit`, or else of the last one.
+    pub fn run_script_with<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<i32, Error> {
+        let status = self.run(executor, env)?;
+        env.catch(Unwind::Exit);
+        Ok(status.map(|s| s.exit_code()).unwrap_or(0))
+    }
+}
+
+/// A block run against a copy of the environment, so nothing it defines, assigns or changes is
+/// visible once it finishes. With `fork`, it runs in a separate process as well.
+#[derive(Debug)]
+pub struct Subshell {
+    block: Block,
+    fork: bool,
+    span: Span,
+}
+
+eq_without_span!(Subshell { block, fork });
+
+impl Subshell {
+    pub fn new(block: Block, fork: bool) -> Subshell {
+        Subshell {
+            block,
+            fork,
+            span: Span::default(),
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Subshell {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<E::ExitStatus, Error> {
+        let mut run = |executor: &mut E| {
+            let mut env = env.clone();
+            let status = self.block.execute(executor, &mut env)?;
+            Ok(status.unwrap_or_else(|| E::ExitStatus::from_code(0)))
+        };
+        if self.fork {
+            nested(executor, format_args!("fork"), |e| e.fork(&mut run))
+        } else {
+            nested(executor, format_args!("subshell"), run)
+        }
     }
 }
 
-fn consume_until_exit<E, I>(iter: I) -> Result<Option<E::ExitStatus>, Error>
+/// `try { } catch { }`: runs the body strictly, and if a statement in it fails or errors, runs the
+/// handler with `$status`, `$command` and `$error` describing what went wrong.
+#[derive(Debug)]
+pub struct Try {
+    body: Block,
+    handler: Block,
+    span: Span,
+}
+
+eq_without_span!(Try { body, handler });
+
+impl Try {
+    pub fn new(body: Block, handler: Block) -> Try {
+        Try {
+            body,
+            handler,
+            span: Span::default(),
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Try {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let outer = env.failure_policy();
+        env.set_failure_policy(FailurePolicy::Strict);
+        let result = nested(executor, format_args!("try"), |e| self.body.execute(e, env));
+        env.set_failure_policy(outer);
+
+        // `return` and `exit` pass straight through, whatever their status.
+        let (status, error) = match result {
+            Ok(Some(status)) if !status.success() && !env.is_unwinding() => {
+                (status.exit_code(), String::new())
+            }
+            Ok(status) => return Ok(status),
+            Err(e) => (1, e.to_string()),
+        };
+
+        env.push_scope();
+        env.define("status", status);
+        env.define("command", env.last_command().unwrap_or_default().to_owned());
+        env.define("error", error);
+        let result = nested(executor, format_args!("catch"), |e| {
+            self.handler.execute(e, env)
+        });
+        env.pop_scope();
+        result
+    }
+}
+
+/// Runs statements until one errors or `keep_going` says to stop after its status, returning the
+/// last status produced.
+fn consume_until_exit<E, I>(
+    iter: I,
+    keep_going: impl Fn(&E::ExitStatus, Span) -> bool,
+) -> Result<Option<E::ExitStatus>, Error>
 where
     E: Executor,
-    I: Iterator<Item = Result<Option<E::ExitStatus>, Error>>,
+    I: Iterator<Item = (Span, Result<Option<E::ExitStatus>, Error>)>,
 {
     let mut last = None;
-    for exit in iter {
-        last = exit?.or(last);
-        if let Some(last) = &last {
-            if !last.success() {
-                break;
+    for (span, exit) in iter {
+        let exit = exit?;
+        if let Some(status) = &exit {
+            if !keep_going(status, span) {
+                return Ok(exit);
             }
         }
+        last = exit.or(last);
     }
     Ok(last)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Runs a loop's `body` with `break` and `continue` able to reach it, inside a scope named after
+/// the loop and its label.
+fn in_loop<E: Executor, T>(
+    executor: &mut E,
+    env: &mut Env,
+    label: &Option<String>,
+    scope: fmt::Arguments,
+    body: impl FnOnce(&mut E, &mut Env) -> T,
+) -> T {
+    env.enter_loop(label.clone());
+    let result = match label {
+        Some(label) => nested(executor, format_args!("{}: {}", label, scope), |e| {
+            body(e, env)
+        }),
+        None => nested(executor, scope, |e| body(e, env)),
+    };
+    env.leave_loop();
+    result
+}
+
+/// Runs `body` inside `scope`, so executors that report on commands can show where they ran.
+fn nested<E: Executor, T>(
+    executor: &mut E,
+    scope: fmt::Arguments,
+    body: impl FnOnce(&mut E) -> T,
+) -> T {
+    executor.enter(&scope);
+    let result = body(executor);
+    executor.leave();
+    result
+}
+
+#[derive(Debug)]
 pub struct While {
-    predicate: Command,
+    predicate: Predicate,
     block: Box<AST>,
+    label: Option<String>,
+    span: Span,
 }
 
+eq_without_span!(While {
+    predicate,
+    block,
+    label
+});
+
 impl While {
-    pub fn new(predicate: Command, block: AST) -> While {
+    pub fn new<P: Into<Predicate>>(predicate: P, block: AST) -> While {
         While {
-            predicate,
+            predicate: predicate.into(),
             block: Box::new(block),
+            label: None,
+            span: Span::default(),
+        }
+    }
+
+    /// Names the loop, so `break` and `continue` in loops inside it can refer to it.
+    pub fn labelled<S: ToString>(mut self, label: S) -> While {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn at(mut self, span: Span) -> While {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        in_loop(
+            executor,
+            env,
+            &self.label,
+            format_args!("while"),
+            |e, env| self.run(e, env),
+        )
+    }
+
+    fn run<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let state = RefCell::new((executor, env));
+        let broken = Cell::new(false);
+
+        let iter = std::iter::repeat(())
+            .take_while(|_| {
+                let env = &mut state.borrow_mut().1;
+                env.check_interrupt();
+                !env.is_unwinding() && !broken.get()
+            })
+            .map(|_| {
+                let (executor, env) = &mut *state.borrow_mut();
+                self.predicate.execute(&mut **executor, env)
+            })
+            .take_while(|pred| {
+                pred.as_ref().map(Success::success).unwrap_or(true)
+                    && !state.borrow().1.is_unwinding()
+            })
+            .enumerate()
+            .map(|(i, check)| {
+                let result = check.and_then(|_| {
+                    let (executor, env) = &mut *state.borrow_mut();
+                    let result =
+                        nested(&mut **executor, format_args!("iteration {}", i + 1), |e| {
+                            self.block.execute_body(e, env)
+                        });
+                    if let Some(Unwind::Break(_)) = env.catch_loop(self.label.as_deref()) {
+                        broken.set(true);
+                    }
+                    result
+                });
+                (self.block.span(), result)
+            });
+
+        let status = consume_until_exit::<E, _>(iter, |status, span| {
+            let env = &state.borrow().1;
+            env.failure_policy().keep_going(status, span, env)
+        })?;
+        Ok(if broken.get() {
+            Some(E::ExitStatus::from_code(0))
+        } else {
+            status
+        })
+    }
+}
+
+/// A named block that can be called like a command, with its arguments bound to `params`.
+#[derive(Debug)]
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Block,
+    span: Span,
+}
+
+eq_without_span!(Function { name, params, body });
+
+impl Function {
+    pub fn new<S: ToString>(name: S, params: Vec<S>, body: Block) -> Function {
+        Function {
+            name: name.to_string(),
+            params: params.into_iter().map(|p| p.to_string()).collect(),
+            body,
+            span: Span::default(),
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Function {
+        self.span = span;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What a `for` loop iterates over.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Items {
+    Words(Vec<Word>),
+    Range {
+        start: Word,
+        end: Word,
+        inclusive: bool,
+    },
+    Lines(Command),
+}
+
+/// The values a `for` loop binds, one at a time.
+type Values = Box<dyn Iterator<Item = String>>;
+
+impl Items {
+    /// Produces the values to bind, or the failing status if the command producing them failed.
+    /// A range counts as the loop goes, so it can be as long as it likes.
+    fn expand<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Result<Values, E::ExitStatus>, Error> {
+        match self {
+            Items::Words(words) => {
+                let mut values = Vec::new();
+                for word in words {
+                    for field in word.expand_fields(executor, env)? {
+                        values.push(into_utf8(field)?);
+                    }
+                }
+                Ok(Ok(Box::new(values.into_iter())))
+            }
+            Items::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = parse_bound(&start.expand_string(executor, env)?)?;
+                let end = parse_bound(&end.expand_string(executor, env)?)?;
+                let values: Values = if *inclusive {
+                    Box::new((start..=end).map(|i| i.to_string()))
+                } else {
+                    Box::new((start..end).map(|i| i.to_string()))
+                };
+                Ok(Ok(values))
+            }
+            Items::Lines(command) => {
+                let (status, output) = command.capture(executor, env)?;
+                if !status.success() {
+                    return Ok(Err(status));
+                }
+                let lines: Vec<_> = output.lines().map(ToString::to_string).collect();
+                Ok(Ok(Box::new(lines.into_iter())))
+            }
+        }
+    }
+}
+
+/// Text for places that need it, like variables, which can't hold arbitrary bytes.
+fn into_utf8(s: OsString) -> Result<String, Error> {
+    s.into_string()
+        .map_err(|s| Error::runtime(format!("not valid UTF-8: {}", s.to_string_lossy())))
+}
+
+/// `$@` inside a larger word, where the arguments can't stay separate, is all of them spaced out.
+fn join(args: &[OsString]) -> OsString {
+    let mut joined = OsString::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            joined.push(" ");
+        }
+        joined.push(arg);
+    }
+    joined
+}
+
+fn parse_bound(s: &str) -> Result<i64, Error> {
+    s.parse()
+        .map_err(|_| Error::runtime(format!("range bound is not an integer: {}", s)))
+}
+
+#[derive(Debug)]
+pub struct For {
+    variable: String,
+    items: Items,
+    block: Box<AST>,
+    label: Option<String>,
+    span: Span,
+}
+
+eq_without_span!(For {
+    variable,
+    items,
+    block,
+    label
+});
+
+impl For {
+    pub fn new<S: ToString>(variable: S, items: Items, block: AST) -> For {
+        For {
+            variable: variable.to_string(),
+            items,
+            block: Box::new(block),
+            label: None,
+            span: Span::default(),
+        }
+    }
+
+    /// Names the loop, so `break` and `continue` in loops inside it can refer to it.
+    pub fn labelled<S: ToString>(mut self, label: S) -> For {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn at(mut self, span: Span) -> For {
+        self.span = span;
+        self
+    }
+
+    fn execute<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let scope = format_args!("for {}", self.variable);
+        in_loop(executor, env, &self.label, scope, |e, env| self.run(e, env))
+    }
+
+    fn run<E: Executor>(
+        &self,
+        executor: &mut E,
+        env: &mut Env,
+    ) -> Result<Option<E::ExitStatus>, Error> {
+        let items = match self.items.expand(executor, env)? {
+            Ok(items) => items,
+            Err(status) => return Ok(Some(status)),
+        };
+
+        let state = RefCell::new((executor, env));
+        let broken = Cell::new(false);
+
+        let iter = items
+            .take_while(|_| {
+                let env = &mut state.borrow_mut().1;
+                env.check_interrupt();
+                !env.is_unwinding() && !broken.get()
+            })
+            .map(|item| {
+                let (executor, env) = &mut *state.borrow_mut();
+                env.push_scope();
+                let scope = format!("{} = {}", self.variable, item);
+                env.define(&self.variable, item);
+                let result = nested(&mut **executor, format_args!("{}", scope), |e| {
+                    self.block.execute_body(e, env)
+                });
+                env.pop_scope();
+                if let Some(Unwind::Break(_)) = env.catch_loop(self.label.as_deref()) {
+                    broken.set(true);
+                }
+                (self.block.span(), result)
+            });
+
+        let status = consume_until_exit::<E, _>(iter, |status, span| {
+            let env = &state.borrow().1;
+            env.failure_policy().keep_going(status, span, env)
+        })?;
+        Ok(if broken.get() {
+            Some(E::ExitStatus::from_code(0))
+        } else {
+            status
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    struct TestExecutor {
+        history: Vec<(String, Vec<String>)>,
+        redirects: Vec<Vec<Redirect<OsString>>>,
+        pipelines: Vec<Vec<String>>,
+        forked_pipelines: Vec<Vec<String>>,
+        dirs: Vec<PathBuf>,
+        exports: Vec<BTreeMap<OsString, OsString>>,
+        forks: usize,
+        future: VecDeque<Future>,
+    }
+
+    impl TestExecutor {
+        fn new() -> TestExecutor {
+            TestExecutor {
+                history: Vec::new(),
+                redirects: Vec::new(),
+                pipelines: Vec::new(),
+                forked_pipelines: Vec::new(),
+                dirs: Vec::new(),
+                exports: Vec::new(),
+                forks: 0,
+                future: VecDeque::new(),
+            }
+        }
+
+        fn last(&self) -> Option<(&str, Vec<&str>)> {
+            self.history
+                .last()
+                .map(|(s, a)| (s.as_ref(), a.iter().map(|s| s.as_ref()).collect()))
+        }
+
+        fn will_fail(&mut self) {
+            self.future.push_back(Future::Fail);
+        }
+
+        fn will_succeed(&mut self) {
+            self.future.push_back(Future::Success);
+        }
+
+        fn will_error(&mut self, error: Error) {
+            self.future.push_back(Future::Error(error));
+        }
+
+        fn will_output(&mut self, output: &str) {
+            self.future.push_back(Future::Output(output.to_string()));
+        }
+
+        fn count(&self, cmd: &str) -> usize {
+            self.history.iter().filter(|(c, _)| c == cmd).count()
+        }
+    }
+
+    enum Future {
+        Fail,
+        Success,
+        Error(Error),
+        Output(String),
+    }
+
+    fn not_found(command: &str) -> Error {
+        Error::Spawn {
+            command: command.to_owned(),
+            error: io::ErrorKind::NotFound.into(),
+        }
+    }
+
+    /// Whether `result` failed because `command` could not be started.
+    fn is_not_found<T>(result: Result<T, Error>, command: &str) -> bool {
+        match result.map(|_| ()).unwrap_err().unlocated() {
+            Error::Spawn { command: c, .. } => c == command,
+            _ => false,
+        }
+    }
+
+    fn lossy(args: &[OsString]) -> Vec<String> {
+        args.iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    impl Executor for TestExecutor {
+        type ExitStatus = bool;
+
+        fn execute(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+            self.capture(invocation).map(|(status, _)| status)
+        }
+
+        fn capture(
+            &mut self,
+            invocation: &Invocation,
+        ) -> Result<(Self::ExitStatus, String), Error> {
+            self.history.push((
+                invocation.command.to_string_lossy().into_owned(),
+                lossy(&invocation.args),
+            ));
+            self.redirects.push(invocation.redirects.clone());
+            self.dirs.push(invocation.dir.clone());
+            self.exports.push(invocation.env.clone());
+            match self.future.pop_front() {
+                None => Ok((true, String::new())),
+                Some(Future::Fail) => Ok((false, String::new())),
+                Some(Future::Success) => Ok((true, String::new())),
+                Some(Future::Error(e)) => Err(e),
+                Some(Future::Output(s)) => Ok((true, s)),
+            }
+        }
+
+        fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Self::ExitStatus>, Error> {
+            self.pipelines.push(
+                stages
+                    .iter()
+                    .map(|s| s.command.to_string_lossy().into_owned())
+                    .collect(),
+            );
+            stages.iter().map(|s| self.execute(s)).collect()
+        }
+
+        /// Stages can't run without a real fork, so only the pipeline is recorded.
+        fn fork_pipeline(
+            &mut self,
+            stages: &[Invocation],
+        ) -> Result<Forked<Self::ExitStatus>, Error> {
+            self.forked_pipelines.push(
+                stages
+                    .iter()
+                    .map(|s| s.command.to_string_lossy().into_owned())
+                    .collect(),
+            );
+            Ok(Forked::Parent(vec![true; stages.len()]))
+        }
+
+        fn fork(
+            &mut self,
+            child: &mut dyn FnMut(&mut Self) -> Result<Self::ExitStatus, Error>,
+        ) -> Result<Self::ExitStatus, Error> {
+            self.forks += 1;
+            child(self)
+        }
+
+        fn internal(
+            &mut self,
+            invocation: &Invocation,
+            inherited: Streams,
+            run: &mut Internal<'_, Self>,
+        ) -> Result<Self::ExitStatus, Error> {
+            let streams = inherited.redirect(&invocation.redirects, &invocation.dir)?;
+            run(self, Some(streams))
+        }
+    }
+
+    impl Success for bool {
+        fn success(&self) -> bool {
+            *self
+        }
+
+        fn from_code(code: i32) -> Self {
+            code == 0
+        }
+
+        fn exit_code(&self) -> i32 {
+            if *self {
+                0
+            } else {
+                1
+            }
+        }
+    }
+
+    fn cmd(s: &str) -> Command {
+        Command::no_args(s)
+    }
+
+    #[cfg(test)]
+    mod command {
+        use super::*;
+
+        #[test]
+        fn executes_using_the_provided_executor() {
+            let mut executor = TestExecutor::new();
+            let command = Command::no_args("foo");
+
+            command.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("foo", vec![])));
+        }
+
+        #[test]
+        fn expands_variables_in_args() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let command = Command::with_words(
+                "echo",
+                vec![Word::new(vec![
+                    WordPart::Literal("hello ".into()),
+                    WordPart::Variable("name".into()),
+                ])],
+            );
+
+            env.define("name", "world");
+            command.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["hello world"])));
+        }
+
+        #[test]
+        fn all_arguments_are_separate_args() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let command = Command::with_words("echo", vec![Word::new(vec![WordPart::Arguments])]);
+
+            env.set_arguments("deploy.cm", vec!["prod".into(), "v1 2".into()]);
+            command.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["prod", "v1 2"])));
+        }
+
+        #[test]
+        fn all_arguments_inside_word_are_joined() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let command = Command::with_words(
+                "echo",
+                vec![Word::new(vec![
+                    WordPart::Literal("args: ".into()),
+                    WordPart::Arguments,
+                ])],
+            );
+
+            env.set_arguments("deploy.cm", vec!["prod".into(), "v1.2".into()]);
+            command.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["args: prod v1.2"])));
+        }
+
+        #[test]
+        fn positional_arguments_and_count() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let command = Command::with_words(
+                "echo",
+                vec![
+                    Word::new(vec![WordPart::Argument(0)]),
+                    Word::new(vec![WordPart::Argument(2)]),
+                    Word::new(vec![WordPart::ArgumentCount]),
+                ],
+            );
+
+            env.set_arguments("deploy.cm", vec!["prod".into(), "v1.2".into()]);
+            command.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(
+                executor.last(),
+                Some(("echo", vec!["deploy.cm", "v1.2", "2"]))
+            );
+        }
+
+        #[test]
+        fn missing_argument_is_error() {
+            let mut executor = TestExecutor::new();
+            let command = Command::with_words("echo", vec![Word::new(vec![WordPart::Argument(1)])]);
+
+            assert!(command.execute(&mut executor, &mut Env::new()).is_err());
+            assert_eq!(executor.count("echo"), 0);
+        }
+
+        #[test]
+        fn home_dir_in_name() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            env.export("HOME", "/home/user");
+            Command::no_args("~/bin/run")
+                .execute(&mut executor, &mut env)
+                .unwrap();
+
+            assert_eq!(executor.last(), Some(("/home/user/bin/run", vec![])));
+        }
+
+        #[test]
+        fn relative_path_is_from_current_dir() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            env.set_current_dir("/").unwrap();
+            Command::no_args("./build.sh")
+                .execute(&mut executor, &mut env)
+                .unwrap();
+
+            assert_eq!(executor.last(), Some(("/./build.sh", vec![])));
+        }
+
+        #[test]
+        fn name_without_slash_is_searched_for() {
+            let mut executor = TestExecutor::new();
+
+            Command::no_args("git-lfs")
+                .execute(&mut executor, &mut Env::new())
+                .unwrap();
+
+            assert_eq!(executor.last(), Some(("git-lfs", vec![])));
+        }
+
+        #[test]
+        fn undefined_variable_is_error() {
+            let mut executor = TestExecutor::new();
+            let command = Command::with_words(
+                "echo",
+                vec![Word::new(vec![WordPart::Variable("name".into())])],
+            );
+
+            assert!(command.execute(&mut executor, &mut Env::new()).is_err());
+            assert_eq!(executor.last(), None);
+        }
+
+        #[test]
+        fn substitutes_command_output() {
+            let mut executor = TestExecutor::new();
+            let command = Command::with_words(
+                "git",
+                vec![
+                    "checkout".into(),
+                    Word::new(vec![WordPart::Command(Box::new(Command::new(
+                        "git",
+                        vec!["rev-parse", "HEAD"],
+                    )))]),
+                ],
+            );
+
+            executor.will_output("abc123\n\n");
+            command.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.history[0].0, "git");
+            assert_eq!(executor.history[0].1, vec!["rev-parse", "HEAD"]);
+            assert_eq!(executor.last(), Some(("git", vec!["checkout", "abc123"])));
+        }
+
+        #[test]
+        fn substitution_keeps_inner_newlines() {
+            let mut executor = TestExecutor::new();
+            let command = Command::with_words(
+                "echo",
+                vec![Word::new(vec![
+                    WordPart::Literal("files: ".into()),
+                    WordPart::Command(Box::new(cmd("ls"))),
+                ])],
+            );
+
+            executor.will_output("a\nb\n");
+            command.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["files: a\nb"])));
+        }
+
+        #[test]
+        fn failed_substitution_is_error() {
+            let mut executor = TestExecutor::new();
+            let command = Command::with_words(
+                "echo",
+                vec![Word::new(vec![WordPart::Command(Box::new(cmd("false")))])],
+            );
+
+            executor.will_fail();
+
+            assert!(command.execute(&mut executor, &mut Env::new()).is_err());
+            assert_eq!(executor.count("echo"), 0);
+        }
+
+        #[test]
+        fn expands_redirect_paths() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let command = cmd("ls").redirect(vec![
+                Redirect::Write {
+                    fd: Fd::Stdout,
+                    path: Word::new(vec![
+                        WordPart::Variable("dir".into()),
+                        WordPart::Literal("/out.txt".into()),
+                    ]),
+                    append: false,
+                },
+                Redirect::StderrToStdout,
+            ]);
+
+            env.define("dir", "/tmp");
+            command.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(
+                executor.redirects,
+                vec![vec![
+                    Redirect::Write {
+                        fd: Fd::Stdout,
+                        path: "/tmp/out.txt".into(),
+                        append: false,
+                    },
+                    Redirect::StderrToStdout,
+                ]]
+            );
+        }
+
+        #[test]
+        fn undefined_variable_in_redirect_is_error() {
+            let mut executor = TestExecutor::new();
+            let command =
+                cmd("cat").redirect(vec![Redirect::Read(Word::new(vec![WordPart::Variable(
+                    "input".into(),
+                )]))]);
+
+            assert!(command.execute(&mut executor, &mut Env::new()).is_err());
+            assert_eq!(executor.last(), None);
+        }
+    }
+
+    #[cfg(test)]
+    mod builtin {
+        use super::*;
+
+        fn temp_file(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("cinnamon-ast-{}-{}", std::process::id(), name))
+        }
+
+        #[test]
+        fn runs_instead_of_executor() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            Command::new("cd", vec!["/"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
+
+            assert_eq!(executor.last(), None);
+            assert_eq!(env.current_dir(), Path::new("/"));
+        }
+
+        #[test]
+        fn commands_run_in_current_dir() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            Command::new("cd", vec!["/"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
+            cmd("ls").execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.dirs, vec![PathBuf::from("/")]);
+        }
+
+        #[test]
+        fn exports_reach_commands() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            Command::new("export", vec!["CINNAMON_TEST=foo"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
+            cmd("env").execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.exports[0][OsStr::new("CINNAMON_TEST")], "foo");
+        }
+
+        #[test]
+        fn failure_is_status() {
+            let mut executor = TestExecutor::new();
+
+            assert!(!Command::new("cd", vec!["/nonexistent"])
+                .execute(&mut executor, &mut Env::new())
+                .unwrap());
+        }
+
+        #[test]
+        fn function_shadows_builtin() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let before = env.current_dir().to_owned();
+
+            env.define_function(Rc::new(Function::new(
+                "cd",
+                vec!["dir"],
+                Block::new(vec![AST::Command(cmd("custom"))]),
+            )));
+            Command::new("cd", vec!["/"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
+
+            assert_eq!(executor.last(), Some(("custom", vec![])));
+            assert_eq!(env.current_dir(), before);
+        }
+
+        #[test]
+        fn custom_builtin() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let calls = Rc::new(Cell::new(0));
+
+            let counter = Rc::clone(&calls);
+            env.define_builtin("count", move |args, _| {
+                counter.set(counter.get() + args.len());
+                Ok(Status::from_code(1))
+            });
+
+            assert!(!Command::new("count", vec!["a", "b"])
+                .execute(&mut executor, &mut env)
+                .unwrap());
+            assert_eq!(calls.get(), 2);
+            assert_eq!(executor.last(), None);
+        }
+
+        #[test]
+        fn output_can_be_substituted() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            env.set_current_dir("/").unwrap();
+            Command::with_words(
+                "echo",
+                vec![Word::new(vec![WordPart::Command(Box::new(cmd("pwd")))])],
+            )
+            .execute(&mut executor, &mut env)
+            .unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["/"])));
+        }
+
+        #[test]
+        fn output_can_be_redirected() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let path = temp_file("pwd");
+
+            env.set_current_dir("/").unwrap();
+            cmd("pwd")
+                .redirect(vec![Redirect::Write {
+                    fd: Fd::Stdout,
+                    path: path.to_str().unwrap().into(),
+                    append: false,
+                }])
+                .execute(&mut executor, &mut env)
+                .unwrap();
+            let output = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(output, "/\n");
+        }
+
+        #[test]
+        fn piped_in_separate_processes() {
+            let mut executor = TestExecutor::new();
+
+            assert!(Pipeline(vec![cmd("pwd"), cmd("cat")])
+                .execute(&mut executor, &mut Env::new())
+                .unwrap());
+            assert_eq!(executor.forked_pipelines, vec![vec!["pwd", "cat"]]);
+            assert!(executor.pipelines.is_empty());
+        }
+
+        #[test]
+        fn source_runs_with_same_executor() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let path = temp_file("source.cm");
+
+            fs::write(&path, "let greeting = hello;\ngreet $greeting;\n").unwrap();
+            let status = Command::new("source", vec![path.to_str().unwrap()])
+                .execute(&mut executor, &mut env);
+            fs::remove_file(&path).unwrap();
+
+            assert!(status.unwrap());
+            assert_eq!(executor.last(), Some(("greet", vec!["hello"])));
+            assert_eq!(env.get("greeting").unwrap(), "hello");
+        }
+
+        #[test]
+        fn subshell_isolates_current_dir() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let before = env.current_dir().to_owned();
+
+            Subshell::new(
+                Block::new(vec![AST::Command(Command::new("cd", vec!["/"]))]),
+                false,
+            )
+            .execute(&mut executor, &mut env)
+            .unwrap();
+
+            assert_eq!(env.current_dir(), before);
+        }
+
+        #[test]
+        fn subshell_isolates_exports() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            Subshell::new(
+                Block::new(vec![AST::Command(Command::new(
+                    "export",
+                    vec!["CINNAMON_TEST=foo"],
+                ))]),
+                false,
+            )
+            .execute(&mut executor, &mut env)
+            .unwrap();
+
+            assert!(env.get("CINNAMON_TEST").is_err());
+        }
+    }
+
+    #[cfg(test)]
+    mod conditional {
+        use super::*;
+
+        #[test]
+        fn executes_if_block_if_predicate_is_true() {
+            let mut executor = TestExecutor::new();
+            let conditional = Conditional::new(cmd("foo"), AST::Command(cmd("bar")), None);
+
+            conditional.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("bar", vec![])));
+        }
+
+        #[test]
+        fn does_not_execute_if_block_if_predicate_is_false() {
+            let mut executor = TestExecutor::new();
+            let conditional = Conditional::new(cmd("foo"), AST::Command(cmd("bar")), None);
+
+            executor.will_fail();
+            conditional.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("foo", vec![])));
+        }
+
+        #[test]
+        fn pipeline_as_predicate() {
+            let mut executor = TestExecutor::new();
+            let conditional = Conditional::new(
+                Predicate::Pipeline(Pipeline(vec![cmd("foo"), cmd("bar")])),
+                AST::Command(cmd("yes")),
+                Some(AST::Command(cmd("no"))),
+            );
+
+            executor.will_succeed();
+            executor.will_fail();
+            conditional.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("no", vec![])));
+        }
+
+        #[test]
+        fn executes_else_block_if_predicate_fails() {
+            let mut executor = TestExecutor::new();
+            let conditional = Conditional::new(
+                cmd("foo"),
+                AST::Command(cmd("bar")),
+                Some(AST::Command(cmd("baz"))),
+            );
+
+            executor.will_fail();
+            conditional.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("baz", vec![])));
+        }
+    }
+
+    #[cfg(test)]
+    mod predicate {
+        use super::*;
+
+        fn pred(s: &str) -> Predicate {
+            Predicate::Command(cmd(s))
+        }
+
+        fn piped(a: &str, b: &str) -> Predicate {
+            Predicate::Pipeline(Pipeline(vec![cmd(a), cmd(b)]))
+        }
+
+        #[test]
+        fn and_after_pipeline_follows_last_stage() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::and(piped("foo", "bar"), pred("baz"));
+
+            executor.will_fail();
+            executor.will_succeed();
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.pipelines, vec![vec!["foo", "bar"]]);
+            assert_eq!(executor.count("baz"), 1);
+        }
+
+        #[test]
+        fn or_after_pipeline_follows_last_stage() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::or(piped("foo", "bar"), pred("baz"));
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("baz"), 1);
+        }
+
+        #[test]
+        fn and_runs_right_if_left_succeeds() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::and(pred("foo"), pred("bar"));
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("bar"), 1);
+        }
+
+        #[test]
+        fn and_skips_right_if_left_fails() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::and(pred("foo"), pred("bar"));
+
+            executor.will_fail();
+
+            assert!(!predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("bar"), 0);
+        }
+
+        #[test]
+        fn and_fails_if_right_fails() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::and(pred("foo"), pred("bar"));
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert!(!predicate.execute(&mut executor, &mut Env::new()).unwrap());
+        }
+
+        #[test]
+        fn or_skips_right_if_left_succeeds() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::or(pred("foo"), pred("bar"));
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("bar"), 0);
+        }
+
+        #[test]
+        fn or_runs_right_if_left_fails() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::or(pred("foo"), pred("bar"));
+
+            executor.will_fail();
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("bar"), 1);
+        }
+
+        #[test]
+        fn not_inverts_status() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let predicate = Predicate::not(pred("foo"));
+
+            executor.will_fail();
+            assert!(predicate.execute(&mut executor, &mut env).unwrap());
+
+            executor.will_succeed();
+            assert!(!predicate.execute(&mut executor, &mut env).unwrap());
+        }
+
+        #[test]
+        fn and_binds_before_or() {
+            let mut executor = TestExecutor::new();
+            // a && !b || c
+            let predicate = Predicate::or(
+                Predicate::and(pred("a"), Predicate::not(pred("b"))),
+                pred("c"),
+            );
+
+            executor.will_fail();
+
+            assert!(predicate.execute(&mut executor, &mut Env::new()).unwrap());
+            assert_eq!(executor.count("b"), 0);
+            assert_eq!(executor.last(), Some(("c", vec![])));
+        }
+
+        #[test]
+        fn error_stops_evaluation() {
+            let mut executor = TestExecutor::new();
+            let predicate = Predicate::or(pred("foo"), pred("bar"));
+
+            executor.will_error(not_found("foo"));
+
+            assert!(is_not_found(
+                predicate.execute(&mut executor, &mut Env::new()),
+                "foo"
+            ));
+            assert_eq!(executor.count("bar"), 0);
+        }
+
+        #[test]
+        fn conditional_short_circuits() {
+            let mut executor = TestExecutor::new();
+            let conditional = Conditional::new(
+                Predicate::and(pred("foo"), pred("bar")),
+                AST::Command(cmd("then")),
+                Some(AST::Command(cmd("else"))),
+            );
+
+            executor.will_fail();
+            conditional.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.count("bar"), 0);
+            assert_eq!(executor.last(), Some(("else", vec![])));
+        }
+
+        #[test]
+        fn while_reevaluates_whole_predicate() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(
+                Predicate::or(pred("foo"), pred("bar")),
+                AST::Command(cmd("body")),
+            );
+
+            // foo fails, bar succeeds, body; foo succeeds, body; foo fails, bar fails.
+            executor.will_fail();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_fail();
+            executor.will_fail();
+            while_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.count("body"), 2);
+            assert_eq!(executor.count("bar"), 2);
+        }
+
+        #[test]
+        fn failing_statement_stops_block() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![
+                AST::Predicate(Predicate::and(pred("foo"), pred("bar"))),
+                AST::Command(cmd("baz")),
+            ]);
+
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+            assert_eq!(executor.count("bar"), 0);
+            assert_eq!(executor.count("baz"), 0);
+        }
+    }
+
+    #[cfg(test)]
+    mod block {
+        use super::*;
+
+        #[test]
+        fn returns_ok_none_with_empty_block() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![]);
+
+            assert_eq!(block.execute(&mut executor, &mut Env::new()).unwrap(), None);
+        }
+
+        #[test]
+        fn returns_ok_true_if_one_success() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![AST::Command(cmd("foo"))]);
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+        }
+
+        #[test]
+        fn returns_ok_false_if_first_fails() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]);
+
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+        }
+
+        #[test]
+        fn returns_ok_false_if_second_fails() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]);
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+        }
+
+        #[test]
+        fn returns_ok_none_if_only_comment() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![AST::Comment(String::from("comment"))]);
+
+            assert_eq!(block.execute(&mut executor, &mut Env::new()).unwrap(), None);
+        }
+
+        #[test]
+        fn returns_err_if_command_errors() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(vec![AST::Command(cmd("foo"))]);
+
+            executor.will_error(not_found("foo"));
+
+            assert!(is_not_found(
+                block.execute(&mut executor, &mut Env::new()),
+                "foo"
+            ));
+        }
+
+        #[test]
+        fn let_does_not_leak_out_of_block() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let block = Block::new(vec![AST::Let(Assignment::new("foo", "bar".into()))]);
+
+            block.execute(&mut executor, &mut env).unwrap();
+
+            assert!(env.get("foo").is_err());
+        }
+
+        #[test]
+        fn assign_updates_enclosing_scope() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let block = Block::new(vec![AST::Assign(Assignment::new("foo", "bar".into()))]);
+
+            env.define("foo", "baz");
+            block.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(env.get("foo").unwrap(), "bar");
+        }
+
+        fn foo_then_bar() -> Vec<AST> {
+            vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]
+        }
+
+        #[test]
+        fn continue_runs_past_failure() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(foo_then_bar()).with_policy(FailurePolicy::Continue);
+
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("bar"), 1);
+        }
+
+        #[test]
+        fn continue_returns_last_status() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(foo_then_bar()).with_policy(FailurePolicy::Continue);
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+        }
+
+        #[test]
+        fn warn_runs_past_failure() {
+            let mut executor = TestExecutor::new();
+            let block = Block::new(foo_then_bar()).with_policy(FailurePolicy::Warn);
+
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("bar"), 1);
+        }
+
+        #[test]
+        fn strict_annotation_overrides_enclosing_policy() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let block = Block::new(foo_then_bar()).with_policy(FailurePolicy::Strict);
+
+            env.set_failure_policy(FailurePolicy::Continue);
+            executor.will_fail();
+
+            assert_eq!(block.execute(&mut executor, &mut env).unwrap(), Some(false));
+            assert_eq!(executor.count("bar"), 0);
+            assert_eq!(env.failure_policy(), FailurePolicy::Continue);
+        }
+
+        #[test]
+        fn enclosing_policy_applies_without_annotation() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let block = Block::new(foo_then_bar());
+
+            env.set_failure_policy(FailurePolicy::Continue);
+            executor.will_fail();
+
+            assert_eq!(block.execute(&mut executor, &mut env).unwrap(), Some(true));
+        }
+
+        #[test]
+        fn pragma_lasts_until_end_of_block() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let mut statements = vec![AST::Pragma(FailurePolicy::Continue)];
+            statements.extend(foo_then_bar());
+            let block = Block::new(statements);
+
+            executor.will_fail();
+
+            assert_eq!(block.execute(&mut executor, &mut env).unwrap(), Some(true));
+            assert_eq!(env.failure_policy(), FailurePolicy::Strict);
+        }
+
+        #[test]
+        fn pragma_does_not_reach_earlier_statements() {
+            let mut executor = TestExecutor::new();
+            let mut statements = foo_then_bar();
+            statements.push(AST::Pragma(FailurePolicy::Continue));
+            let block = Block::new(statements);
+
+            executor.will_fail();
+
+            assert_eq!(
+                block.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+            assert_eq!(executor.count("bar"), 0);
+        }
+    }
+
+    #[cfg(test)]
+    mod try_ {
+        use super::*;
+
+        fn report(variable: &str) -> AST {
+            AST::Command(Command::with_words(
+                "report",
+                vec![Word::new(vec![WordPart::Variable(variable.into())])],
+            ))
+        }
+
+        fn try_(handler: Vec<AST>) -> Try {
+            Try::new(
+                Block::new(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]),
+                Block::new(handler),
+            )
+        }
+
+        #[test]
+        fn handler_skipped_on_success() {
+            let mut executor = TestExecutor::new();
+
+            assert_eq!(
+                try_(vec![report("status")])
+                    .execute(&mut executor, &mut Env::new())
+                    .unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("report"), 0);
+        }
+
+        #[test]
+        fn failure_runs_handler() {
+            let mut executor = TestExecutor::new();
+            let try_ = try_(vec![report("status"), report("command")]);
+
+            executor.will_fail();
+
+            assert_eq!(
+                try_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("bar"), 0);
+            assert_eq!(
+                executor.history[1..],
+                [
+                    ("report".to_owned(), vec!["1".to_owned()]),
+                    ("report".to_owned(), vec!["foo".to_owned()]),
+                ]
+            );
+        }
+
+        #[test]
+        fn command_is_empty_if_body_fails_before_running_one() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let try_ = Try::new(
+                Block::new(vec![AST::Command(Command::with_words(
+                    "foo",
+                    vec![Word::new(vec![WordPart::Variable("undefined".into())])],
+                ))]),
+                Block::new(vec![report("command")]),
+            );
+
+            env.set_last_command("before".to_owned());
+            try_.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("report", vec![""])));
+        }
+
+        #[test]
+        fn failed_call_is_command() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let body = Block::new(vec![AST::Command(cmd("push"))]);
+            env.define_function(Rc::new(Function::new("deploy", vec!["target"], body)));
+            let try_ = Try::new(
+                Block::new(vec![AST::Command(Command::new("deploy", vec!["prod"]))]),
+                Block::new(vec![report("command")]),
+            );
+
+            executor.will_fail();
+            try_.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("report", vec!["deploy prod"])));
+        }
+
+        #[test]
+        fn error_runs_handler() {
+            let mut executor = TestExecutor::new();
+            let try_ = try_(vec![report("error")]);
+
+            executor.will_error(not_found("foo"));
+            try_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(
+                executor.last(),
+                Some(("report", vec!["command not found: foo"]))
+            );
+        }
+
+        #[test]
+        fn body_is_strict_under_continue() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            env.set_failure_policy(FailurePolicy::Continue);
+            executor.will_fail();
+            try_(vec![]).execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.count("bar"), 0);
+            assert_eq!(env.failure_policy(), FailurePolicy::Continue);
+        }
+
+        #[test]
+        fn handler_status_is_result() {
+            let mut executor = TestExecutor::new();
+
+            executor.will_fail();
+            executor.will_fail();
+
+            assert_eq!(
+                try_(vec![AST::Command(cmd("handle"))])
+                    .execute(&mut executor, &mut Env::new())
+                    .unwrap(),
+                Some(false)
+            );
+        }
+
+        #[test]
+        fn handler_variables_do_not_leak() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            executor.will_fail();
+            try_(vec![]).execute(&mut executor, &mut env).unwrap();
+
+            assert!(env.get("status").is_err());
+        }
+    }
+
+    #[cfg(test)]
+    mod subshell {
+        use super::*;
+
+        #[test]
+        fn runs_block() {
+            let mut executor = TestExecutor::new();
+            let subshell = Subshell::new(Block::new(vec![AST::Command(cmd("foo"))]), false);
+
+            subshell.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.last(), Some(("foo", vec![])));
+            assert_eq!(executor.forks, 0);
+        }
+
+        #[test]
+        fn assignments_do_not_leak() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let subshell = Subshell::new(
+                Block::new(vec![AST::Assign(Assignment::new("foo", "inner".into()))]),
+                false,
+            );
+
+            env.define("foo", "outer");
+            subshell.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(env.get("foo").unwrap(), "outer");
+        }
+
+        #[test]
+        fn reads_enclosing_variables() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let subshell = Subshell::new(
+                Block::new(vec![AST::Command(Command::with_words(
+                    "echo",
+                    vec![Word::new(vec![WordPart::Variable("foo".into())])],
+                ))]),
+                false,
+            );
+
+            env.define("foo", "bar");
+            subshell.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("echo", vec!["bar"])));
+        }
+
+        #[test]
+        fn functions_do_not_leak() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let subshell = Subshell::new(
+                Block::new(vec![AST::Function(Rc::new(Function::new(
+                    "greet",
+                    vec![],
+                    Block::new(vec![]),
+                )))]),
+                false,
+            );
+
+            subshell.execute(&mut executor, &mut env).unwrap();
+
+            assert!(env.function("greet").is_none());
+        }
+
+        #[test]
+        fn status_of_block() {
+            let mut executor = TestExecutor::new();
+            let subshell = Subshell::new(
+                Block::new(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]),
+                false,
+            );
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert!(!subshell.execute(&mut executor, &mut Env::new()).unwrap());
+        }
+
+        #[test]
+        fn empty_succeeds() {
+            let mut executor = TestExecutor::new();
+            let subshell = Subshell::new(Block::new(vec![]), false);
+
+            assert!(subshell.execute(&mut executor, &mut Env::new()).unwrap());
+        }
+
+        #[test]
+        fn fork_uses_executor() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let subshell = Subshell::new(
+                Block::new(vec![
+                    AST::Assign(Assignment::new("foo", "inner".into())),
+                    AST::Command(cmd("bar")),
+                ]),
+                true,
+            );
+
+            env.define("foo", "outer");
+            subshell.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.forks, 1);
+            assert_eq!(executor.last(), Some(("bar", vec![])));
+            assert_eq!(env.get("foo").unwrap(), "outer");
+        }
+
+        #[test]
+        fn return_only_leaves_subshell() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            AST::Function(Rc::new(Function::new(
+                "check",
+                vec![],
+                Block::new(vec![
+                    AST::Subshell(Subshell::new(
+                        Block::new(vec![AST::Return(None, Span::default())]),
+                        false,
+                    )),
+                    AST::Command(cmd("after")),
+                ]),
+            )))
+            .execute_with(&mut executor, &mut env)
+            .unwrap();
+            cmd("check").execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.count("after"), 1);
+        }
+    }
+
+    #[cfg(test)]
+    mod script {
+        use super::*;
+
+        fn run(statements: Vec<AST>, executor: &mut TestExecutor) -> i32 {
+            Block::new(statements)
+                .run_script_with(executor, &mut Env::new())
+                .unwrap()
+        }
+
+        #[test]
+        fn empty_exits_zero() {
+            assert_eq!(run(vec![], &mut TestExecutor::new()), 0);
+        }
+
+        #[test]
+        fn status_of_last_statement() {
+            let mut executor = TestExecutor::new();
+
+            executor.will_succeed();
+            executor.will_succeed();
+
+            assert_eq!(
+                run(
+                    vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))],
+                    &mut executor
+                ),
+                0
+            );
+        }
+
+        #[test]
+        fn stops_at_first_failure() {
+            let mut executor = TestExecutor::new();
+
+            executor.will_fail();
+
+            assert_eq!(
+                run(
+                    vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))],
+                    &mut executor
+                ),
+                1
+            );
+            assert_eq!(executor.count("bar"), 0);
+        }
+
+        #[test]
+        fn exit_sets_code() {
+            let mut executor = TestExecutor::new();
+
+            assert_eq!(
+                run(
+                    vec![
+                        AST::Command(Command::new("exit", vec!["0"])),
+                        AST::Command(cmd("foo"))
+                    ],
+                    &mut executor
+                ),
+                0
+            );
+            assert_eq!(executor.count("foo"), 0);
+        }
+
+        #[test]
+        fn exit_with_failure() {
+            let mut executor = TestExecutor::new();
+
+            assert_eq!(
+                run(
+                    vec![AST::Command(Command::new("exit", vec!["3"]))],
+                    &mut executor
+                ),
+                1
+            );
+        }
+
+        #[test]
+        fn bare_exit_succeeds() {
+            let mut executor = TestExecutor::new();
+
+            assert_eq!(
+                run(
+                    vec![AST::Command(cmd("exit")), AST::Command(cmd("foo"))],
+                    &mut executor
+                ),
+                0
+            );
+            assert_eq!(executor.count("foo"), 0);
+        }
+
+        #[test]
+        fn exit_leaves_functions_and_loops() {
+            let mut executor = TestExecutor::new();
+
+            run(
+                vec![
+                    AST::Function(Rc::new(Function::new(
+                        "quit",
+                        vec![],
+                        Block::new(vec![
+                            AST::Command(Command::new("exit", vec!["0"])),
+                            AST::Command(cmd("in_function")),
+                        ]),
+                    ))),
+                    AST::For(For::new(
+                        "x",
+                        Items::Words(vec!["a".into(), "b".into()]),
+                        AST::Block(Block::new(vec![
+                            AST::Command(cmd("quit")),
+                            AST::Command(cmd("in_loop")),
+                        ])),
+                    )),
+                    AST::Command(cmd("after")),
+                ],
+                &mut executor,
+            );
+
+            assert_eq!(executor.count("in_function"), 0);
+            assert_eq!(executor.count("in_loop"), 0);
+            assert_eq!(executor.count("after"), 0);
+        }
+
+        #[test]
+        fn exit_skips_rest_of_predicate() {
+            let mut executor = TestExecutor::new();
+
+            run(
+                vec![
+                    AST::Function(Rc::new(Function::new(
+                        "quit",
+                        vec![],
+                        Block::new(vec![AST::Command(Command::new("exit", vec!["0"]))]),
+                    ))),
+                    AST::If(Conditional::new(
+                        Predicate::and(cmd("quit").into(), cmd("foo").into()),
+                        AST::Command(cmd("bar")),
+                        None,
+                    )),
+                ],
+                &mut executor,
+            );
+
+            assert_eq!(executor.count("foo"), 0);
+            assert_eq!(executor.count("bar"), 0);
+        }
+
+        #[test]
+        fn exit_only_leaves_subshell() {
+            let mut executor = TestExecutor::new();
+
+            assert_eq!(
+                run(
+                    vec![
+                        AST::Subshell(Subshell::new(
+                            Block::new(vec![AST::Command(cmd("exit"))]),
+                            false
+                        )),
+                        AST::Command(cmd("after")),
+                    ],
+                    &mut executor
+                ),
+                0
+            );
+            assert_eq!(executor.count("after"), 1);
+        }
+    }
+
+    #[cfg(test)]
+    mod while_ {
+        use super::*;
+
+        #[test]
+        fn continue_keeps_looping_after_failure() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            env.set_failure_policy(FailurePolicy::Continue);
+            executor.will_succeed();
+            executor.will_fail();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(while_.execute(&mut executor, &mut env).unwrap(), Some(true));
+            assert_eq!(executor.count("bar"), 2);
+        }
+
+        #[test]
+        fn returns_ok_none_if_predicate_fails() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Block(Block::new(vec![])));
+
+            executor.will_fail();
+
+            assert_eq!(
+                while_.execute(&mut executor, &mut Env::new()).unwrap(),
+                None
+            );
+        }
+
+        #[test]
+        fn executes_block_once() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_fail();
+
+            while_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.count("bar"), 1);
+        }
+
+        #[test]
+        fn executes_block_thrice() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            executor.will_succeed();
+            executor.will_succeed();
+
+            executor.will_succeed();
+            executor.will_succeed();
+
+            executor.will_succeed();
+            executor.will_succeed();
+
+            executor.will_fail();
+
+            while_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(executor.count("bar"), 3);
+        }
+
+        #[test]
+        fn block_failure_breaks_loop() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(
+                while_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+        }
+
+        #[test]
+        fn one_loop_returns_last() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(
+                while_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+        }
+
+        #[test]
+        fn error_in_predicate_breaks() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+
+            executor.will_error(not_found("foo"));
+
+            assert!(is_not_found(
+                while_.execute(&mut executor, &mut Env::new()),
+                "foo"
+            ));
+        }
+
+        #[test]
+        fn break_leaves_loop_with_success() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let while_ = While::new(
+                cmd("foo"),
+                AST::Block(Block::new(vec![
+                    AST::Command(cmd("bar")),
+                    AST::Break(None, Span::default()),
+                ])),
+            );
+
+            env.set_failure_policy(FailurePolicy::Continue);
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(while_.execute(&mut executor, &mut env).unwrap(), Some(true));
+            assert_eq!(executor.count("foo"), 1);
+            assert!(!env.is_unwinding());
+        }
+
+        #[test]
+        fn continue_skips_rest_of_body() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(
+                cmd("foo"),
+                AST::Block(Block::new(vec![
+                    AST::Command(cmd("bar")),
+                    AST::Continue(None, Span::default()),
+                    AST::Command(cmd("baz")),
+                ])),
+            );
+
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_fail();
+
+            assert_eq!(
+                while_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("bar"), 2);
+            assert_eq!(executor.count("baz"), 0);
+        }
+
+        #[test]
+        fn break_inside_conditional() {
+            let mut executor = TestExecutor::new();
+            let while_ = While::new(
+                cmd("foo"),
+                AST::Block(Block::new(vec![
+                    AST::If(Conditional::new(
+                        cmd("bar"),
+                        AST::Break(None, Span::default()),
+                        None,
+                    )),
+                    AST::Command(cmd("baz")),
+                ])),
+            );
+
+            executor.will_succeed();
+            executor.will_fail();
+            executor.will_succeed();
+            executor.will_succeed();
+            executor.will_succeed();
+
+            assert_eq!(
+                while_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(executor.count("baz"), 1);
+        }
+    }
+
+    #[cfg(test)]
+    mod for_ {
+        use super::*;
+
+        fn echo_var() -> AST {
+            AST::Command(Command::with_words(
+                "echo",
+                vec![Word::new(vec![WordPart::Variable("x".into())])],
+            ))
+        }
+
+        fn args(executor: &TestExecutor) -> Vec<String> {
+            executor
+                .history
+                .iter()
+                .filter(|(c, _)| c == "echo")
+                .map(|(_, a)| a.join(" "))
+                .collect()
+        }
+
+        #[test]
+        fn iterates_words() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new(
+                "x",
+                Items::Words(vec!["a".into(), "b".into(), "c".into()]),
+                echo_var(),
+            );
+
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["a", "b", "c"]);
+        }
+
+        #[test]
+        fn iterates_arguments() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let for_ = For::new(
+                "x",
+                Items::Words(vec![Word::new(vec![WordPart::Arguments]), "c".into()]),
+                echo_var(),
+            );
+
+            env.set_arguments("script.cm", vec!["a".into(), "b".into()]);
+            for_.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(args(&executor), vec!["a", "b", "c"]);
+        }
+
+        #[test]
+        fn empty_list_returns_none() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new("x", Items::Words(vec![]), echo_var());
+
+            assert_eq!(for_.execute(&mut executor, &mut Env::new()).unwrap(), None);
+        }
+
+        #[test]
+        fn exclusive_range() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new(
+                "x",
+                Items::Range {
+                    start: "1".into(),
+                    end: "4".into(),
+                    inclusive: false,
+                },
+                echo_var(),
+            );
+
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["1", "2", "3"]);
+        }
+
+        #[test]
+        fn inclusive_range() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new(
+                "x",
+                Items::Range {
+                    start: "1".into(),
+                    end: "3".into(),
+                    inclusive: true,
+                },
+                echo_var(),
+            );
+
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["1", "2", "3"]);
+        }
+
+        #[test]
+        fn huge_range_runs_as_it_goes() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new(
+                "x",
+                Items::Range {
+                    start: "0".into(),
+                    end: i64::MAX.to_string().as_str().into(),
+                    inclusive: true,
+                },
+                AST::Block(Block::new(vec![
+                    AST::Command(Command::with_words(
+                        "echo",
+                        vec![Word::new(vec![WordPart::Variable("x".into())])],
+                    )),
+                    AST::Break(None, Span::default()),
+                ])),
+            );
+
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["0"]);
+        }
+
+        #[test]
+        fn non_integer_range_is_error() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new(
+                "x",
+                Items::Range {
+                    start: "1".into(),
+                    end: "foo".into(),
+                    inclusive: false,
+                },
+                echo_var(),
+            );
+
+            assert!(for_.execute(&mut executor, &mut Env::new()).is_err());
+        }
+
+        #[test]
+        fn iterates_command_lines() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new("x", Items::Lines(cmd("ls")), echo_var());
+
+            executor.will_output("foo\nbar baz\n");
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["foo", "bar baz"]);
+        }
+
+        #[test]
+        fn failing_command_skips_body() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new("x", Items::Lines(cmd("ls")), echo_var());
+
+            executor.will_fail();
+
+            assert_eq!(
+                for_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+            assert_eq!(executor.count("echo"), 0);
         }
-    }
 
-    fn execute<E: Executor>(&self, executor: &mut E) -> Result<Option<E::ExitStatus>, Error> {
-        let executor = std::cell::RefCell::new(executor);
+        #[test]
+        fn body_failure_breaks_loop() {
+            let mut executor = TestExecutor::new();
+            let for_ = For::new("x", Items::Words(vec!["a".into(), "b".into()]), echo_var());
 
-        let iter = std::iter::repeat(())
-            .map(|_| self.predicate.execute(&mut **executor.borrow_mut()))
-            .take_while(|pred| pred.as_ref().map(Success::success).unwrap_or(true))
-            .map(|check| {
-                check?;
-                self.block.execute_with(&mut **executor.borrow_mut())
-            });
+            executor.will_fail();
 
-        consume_until_exit::<E, _>(iter)
-    }
-}
+            assert_eq!(
+                for_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(false)
+            );
+            assert_eq!(executor.count("echo"), 1);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::VecDeque;
+        #[test]
+        fn variable_does_not_leak() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let for_ = For::new("x", Items::Words(vec!["a".into()]), echo_var());
 
-    struct TestExecutor {
-        history: Vec<(String, Vec<String>)>,
-        future: VecDeque<Future>,
-    }
+            for_.execute(&mut executor, &mut env).unwrap();
 
-    impl TestExecutor {
-        fn new() -> TestExecutor {
-            TestExecutor {
-                history: Vec::new(),
-                future: VecDeque::new(),
-            }
+            assert!(env.get("x").is_err());
         }
 
-        fn last(&self) -> Option<(&str, Vec<&str>)> {
-            self.history
-                .last()
-                .map(|(s, a)| (s.as_ref(), a.iter().map(|s| s.as_ref()).collect()))
+        fn nested(inner: AST) -> For {
+            let words = || Items::Words(vec!["a".into(), "b".into()]);
+            let inner = For::new(
+                "y",
+                words(),
+                AST::Block(Block::new(vec![echo_var(), inner])),
+            );
+            For::new("x", words(), AST::For(inner)).labelled("outer")
         }
 
-        fn will_fail(&mut self) {
-            self.future.push_back(Future::Fail);
-        }
+        #[test]
+        fn labelled_break_leaves_outer_loop() {
+            let mut executor = TestExecutor::new();
+            let for_ = nested(AST::Break(Some("outer".into()), Span::default()));
 
-        fn will_succeed(&mut self) {
-            self.future.push_back(Future::Success);
+            assert_eq!(
+                for_.execute(&mut executor, &mut Env::new()).unwrap(),
+                Some(true)
+            );
+            assert_eq!(args(&executor), vec!["a"]);
         }
 
-        fn will_error(&mut self, error: Error) {
-            self.future.push_back(Future::Error(error));
-        }
+        #[test]
+        fn labelled_continue_goes_on_with_outer_loop() {
+            let mut executor = TestExecutor::new();
+            let for_ = nested(AST::Continue(Some("outer".into()), Span::default()));
 
-        fn count(&self, cmd: &str) -> usize {
-            self.history.iter().filter(|(c, _)| c == cmd).count()
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
+
+            assert_eq!(args(&executor), vec!["a", "b"]);
         }
-    }
 
-    enum Future {
-        Fail,
-        Success,
-        Error(Error),
-    }
+        #[test]
+        fn unlabelled_break_leaves_inner_loop() {
+            let mut executor = TestExecutor::new();
+            let for_ = nested(AST::Break(None, Span::default()));
 
-    impl Executor for TestExecutor {
-        type ExitStatus = bool;
+            for_.execute(&mut executor, &mut Env::new()).unwrap();
 
-        fn execute(&mut self, command: &str, args: &[&str]) -> Result<Self::ExitStatus, Error> {
-            self.history.push((
-                command.to_string(),
-                args.iter().map(|s| s.to_string()).collect(),
-            ));
-            match self.future.pop_front() {
-                None => Ok(true),
-                Some(Future::Fail) => Ok(false),
-                Some(Future::Success) => Ok(true),
-                Some(Future::Error(e)) => Err(e),
-            }
+            assert_eq!(args(&executor), vec!["a", "b"]);
         }
-    }
 
-    impl Success for bool {
-        fn success(&self) -> bool {
-            *self
-        }
-    }
+        #[test]
+        fn unknown_label_is_error() {
+            let mut executor = TestExecutor::new();
+            let for_ = nested(AST::Break(Some("inner".into()), Span::default()));
 
-    fn cmd(s: &str) -> Command {
-        Command::no_args(s)
-    }
+            let error = for_.execute(&mut executor, &mut Env::new()).unwrap_err();
 
-    #[cfg(test)]
-    mod command {
-        use super::*;
+            assert_eq!(
+                error.to_string(),
+                "break inner: no enclosing loop has that label"
+            );
+            assert_eq!(executor.count("echo"), 1);
+        }
 
         #[test]
-        fn executes_using_the_provided_executor() {
+        fn break_outside_loop_is_error() {
             let mut executor = TestExecutor::new();
-            let command = Command::no_args("foo");
 
-            command.execute(&mut executor).unwrap();
+            let error = AST::Break(None, Span::default())
+                .execute_with(&mut executor, &mut Env::new())
+                .unwrap_err();
 
-            assert_eq!(executor.last(), Some(("foo", vec![])));
+            assert_eq!(error.to_string(), "break outside of a loop");
         }
     }
 
     #[cfg(test)]
-    mod conditional {
+    mod pipeline {
         use super::*;
 
+        fn pipeline() -> Pipeline {
+            Pipeline(vec![
+                Command::new("cat", vec!["foo"]),
+                cmd("grep"),
+                cmd("wc"),
+            ])
+        }
+
         #[test]
-        fn executes_if_block_if_predicate_is_true() {
+        fn runs_stages_as_one_pipeline() {
             let mut executor = TestExecutor::new();
-            let conditional = Conditional::new(cmd("foo"), AST::Command(cmd("bar")), None);
 
-            conditional.execute(&mut executor).unwrap();
+            pipeline().execute(&mut executor, &mut Env::new()).unwrap();
 
-            assert_eq!(executor.last(), Some(("bar", vec![])));
+            assert_eq!(executor.pipelines, vec![vec!["cat", "grep", "wc"]]);
+            assert_eq!(executor.history[0], ("cat".into(), vec!["foo".into()]));
         }
 
         #[test]
-        fn does_not_execute_if_block_if_predicate_is_false() {
+        fn expands_variables_in_each_stage() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+            let pipeline = Pipeline(vec![
+                cmd("cat"),
+                Command::with_words(
+                    "grep",
+                    vec![Word::new(vec![WordPart::Variable("pattern".into())])],
+                ),
+            ]);
+
+            env.define("pattern", "foo");
+            pipeline.execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.last(), Some(("grep", vec!["foo"])));
+        }
+
+        #[test]
+        fn status_of_last_stage_by_default() {
             let mut executor = TestExecutor::new();
-            let conditional = Conditional::new(cmd("foo"), AST::Command(cmd("bar")), None);
 
             executor.will_fail();
-            conditional.execute(&mut executor).unwrap();
+            executor.will_succeed();
+            executor.will_succeed();
 
-            assert_eq!(executor.last(), Some(("foo", vec![])));
+            assert!(pipeline().execute(&mut executor, &mut Env::new()).unwrap());
         }
 
         #[test]
-        fn executes_else_block_if_predicate_fails() {
+        fn any_failure_fails_pipeline() {
             let mut executor = TestExecutor::new();
-            let conditional = Conditional::new(
-                cmd("foo"),
-                AST::Command(cmd("bar")),
-                Some(AST::Command(cmd("baz"))),
-            );
+            let mut env = Env::new();
 
+            env.set_pipe_status(PipeStatus::AnyFailure);
             executor.will_fail();
-            conditional.execute(&mut executor).unwrap();
+            executor.will_succeed();
+            executor.will_succeed();
 
-            assert_eq!(executor.last(), Some(("baz", vec![])));
+            assert!(!pipeline().execute(&mut executor, &mut env).unwrap());
+        }
+
+        #[test]
+        fn any_failure_succeeds_if_all_succeed() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            env.set_pipe_status(PipeStatus::AnyFailure);
+
+            assert!(pipeline().execute(&mut executor, &mut env).unwrap());
+        }
+
+        #[test]
+        fn error_in_stage_is_error() {
+            let mut executor = TestExecutor::new();
+
+            executor.will_succeed();
+            executor.will_error(not_found("grep"));
+
+            assert!(is_not_found(
+                pipeline().execute(&mut executor, &mut Env::new()),
+                "grep"
+            ));
         }
     }
 
     #[cfg(test)]
-    mod block {
+    mod function {
         use super::*;
 
+        fn var(name: &str) -> Word {
+            Word::new(vec![WordPart::Variable(name.into())])
+        }
+
+        fn define(env: &mut Env, name: &str, params: Vec<&str>, body: Vec<AST>) {
+            let mut executor = TestExecutor::new();
+            AST::Function(Rc::new(Function::new(name, params, Block::new(body))))
+                .execute_with(&mut executor, env)
+                .unwrap();
+        }
+
         #[test]
-        fn returns_ok_none_with_empty_block() {
+        fn call_runs_body_instead_of_command() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![]);
+            let mut env = Env::new();
 
-            assert_eq!(block.execute(&mut executor).unwrap(), None);
+            define(&mut env, "greet", vec![], vec![AST::Command(cmd("hello"))]);
+            cmd("greet").execute(&mut executor, &mut env).unwrap();
+
+            assert_eq!(executor.count("greet"), 0);
+            assert_eq!(executor.last(), Some(("hello", vec![])));
         }
 
         #[test]
-        fn returns_ok_true_if_one_success() {
+        fn binds_params() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![AST::Command(cmd("foo"))]);
+            let mut env = Env::new();
+
+            define(
+                &mut env,
+                "deploy",
+                vec!["env", "tag"],
+                vec![AST::Command(Command::with_words(
+                    "push",
+                    vec![var("env"), var("tag")],
+                ))],
+            );
+            Command::new("deploy", vec!["prod", "v1.2"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
 
-            assert_eq!(block.execute(&mut executor).unwrap(), Some(true));
+            assert_eq!(executor.last(), Some(("push", vec!["prod", "v1.2"])));
+            assert!(env.get("env").is_err());
         }
 
         #[test]
-        fn returns_ok_false_if_first_fails() {
+        fn break_does_not_leave_the_callers_loop() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]);
+            let mut env = Env::new();
+            let for_ = For::new(
+                "x",
+                Items::Words(vec!["a".into()]),
+                AST::Command(cmd("stop")),
+            );
 
-            executor.will_fail();
+            define(
+                &mut env,
+                "stop",
+                vec![],
+                vec![AST::Continue(None, Span::default())],
+            );
+            let error = for_.execute(&mut executor, &mut env).unwrap_err();
 
-            assert_eq!(block.execute(&mut executor).unwrap(), Some(false));
+            assert_eq!(error.to_string(), "continue outside of a loop");
         }
 
         #[test]
-        fn returns_ok_false_if_second_fails() {
+        fn wrong_number_of_arguments_is_error() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![AST::Command(cmd("foo")), AST::Command(cmd("bar"))]);
+            let mut env = Env::new();
 
-            executor.will_succeed();
-            executor.will_fail();
+            define(&mut env, "deploy", vec!["env"], vec![]);
 
-            assert_eq!(block.execute(&mut executor).unwrap(), Some(false));
+            assert!(cmd("deploy").execute(&mut executor, &mut env).is_err());
         }
 
         #[test]
-        fn returns_ok_none_if_only_comment() {
+        fn status_of_last_statement() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![AST::Comment(String::from("comment"))]);
+            let mut env = Env::new();
+
+            define(&mut env, "check", vec![], vec![AST::Command(cmd("test"))]);
+            executor.will_fail();
 
-            assert_eq!(block.execute(&mut executor).unwrap(), None);
+            assert!(!cmd("check").execute(&mut executor, &mut env).unwrap());
         }
 
         #[test]
-        fn returns_err_if_command_errors() {
+        fn empty_body_succeeds() {
             let mut executor = TestExecutor::new();
-            let block = Block(vec![AST::Command(cmd("foo"))]);
+            let mut env = Env::new();
 
-            executor.will_error(failure::err_msg("error"));
+            define(&mut env, "nothing", vec![], vec![]);
 
-            assert!(block.execute(&mut executor).is_err());
+            assert!(cmd("nothing").execute(&mut executor, &mut env).unwrap());
         }
-    }
 
-    #[cfg(test)]
-    mod while_ {
-        use super::*;
+        #[test]
+        fn return_stops_body_with_status() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            define(
+                &mut env,
+                "check",
+                vec![],
+                vec![
+                    AST::Return(Some("1".into()), Span::default()),
+                    AST::Command(cmd("unreachable")),
+                ],
+            );
+
+            assert!(!cmd("check").execute(&mut executor, &mut env).unwrap());
+            assert_eq!(executor.count("unreachable"), 0);
+            assert!(!env.is_unwinding());
+        }
 
         #[test]
-        fn returns_ok_none_if_predicate_fails() {
+        fn return_from_inside_loop() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Block(Block(vec![])));
+            let mut env = Env::new();
+
+            define(
+                &mut env,
+                "find",
+                vec![],
+                vec![AST::While(While::new(
+                    cmd("true"),
+                    AST::Block(Block::new(vec![
+                        AST::Return(Some("0".into()), Span::default()),
+                        AST::Command(cmd("unreachable")),
+                    ])),
+                ))],
+            );
 
-            executor.will_fail();
+            assert!(cmd("find").execute(&mut executor, &mut env).unwrap());
+            assert_eq!(executor.count("true"), 1);
+            assert_eq!(executor.count("unreachable"), 0);
+        }
+
+        #[test]
+        fn bare_return_keeps_last_status() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            define(
+                &mut env,
+                "check",
+                vec![],
+                vec![
+                    AST::Command(cmd("test")),
+                    AST::Return(None, Span::default()),
+                ],
+            );
+            executor.will_succeed();
 
-            assert_eq!(while_.execute(&mut executor).unwrap(), None);
+            assert!(cmd("check").execute(&mut executor, &mut env).unwrap());
         }
 
         #[test]
-        fn executes_block_once() {
+        fn return_outside_function_is_error() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
 
+            assert!(AST::Return(None, Span::default())
+                .execute_with(&mut executor, &mut Env::new())
+                .is_err());
+        }
+
+        #[test]
+        fn recursion() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
+
+            // fn countdown(n) { if test $n { tick; countdown $n; } }
+            define(
+                &mut env,
+                "countdown",
+                vec!["n"],
+                vec![AST::If(Conditional::new(
+                    Command::with_words("test", vec![var("n")]),
+                    AST::Block(Block::new(vec![
+                        AST::Command(cmd("tick")),
+                        AST::Command(Command::with_words("countdown", vec![var("n")])),
+                    ])),
+                    None,
+                ))],
+            );
+            executor.will_succeed();
+            executor.will_succeed();
             executor.will_succeed();
             executor.will_succeed();
             executor.will_fail();
 
-            while_.execute(&mut executor).unwrap();
+            Command::new("countdown", vec!["3"])
+                .execute(&mut executor, &mut env)
+                .unwrap();
 
-            assert_eq!(executor.count("bar"), 1);
+            assert_eq!(executor.count("tick"), 2);
         }
 
         #[test]
-        fn executes_block_thrice() {
+        fn unbounded_recursion_is_error() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+            let mut env = Env::new();
 
-            executor.will_succeed();
-            executor.will_succeed();
+            define(
+                &mut env,
+                "forever",
+                vec![],
+                vec![AST::Command(cmd("forever"))],
+            );
 
-            executor.will_succeed();
-            executor.will_succeed();
+            assert!(cmd("forever").execute(&mut executor, &mut env).is_err());
+        }
 
-            executor.will_succeed();
-            executor.will_succeed();
+        #[test]
+        fn body_is_strict_under_lenient_caller() {
+            let mut executor = TestExecutor::new();
+            let mut env = Env::new();
 
+            define(
+                &mut env,
+                "check",
+                vec![],
+                vec![AST::Command(cmd("test")), AST::Command(cmd("after"))],
+            );
+            env.set_failure_policy(FailurePolicy::Continue);
             executor.will_fail();
 
-            while_.execute(&mut executor).unwrap();
-
-            assert_eq!(executor.count("bar"), 3);
+            assert!(!cmd("check").execute(&mut executor, &mut env).unwrap());
+            assert_eq!(executor.count("after"), 0);
+            assert_eq!(env.failure_policy(), FailurePolicy::Continue);
         }
 
         #[test]
-        fn block_failure_breaks_loop() {
+        fn body_keeps_its_own_annotation() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
-
-            executor.will_succeed();
+            let mut env = Env::new();
+
+            AST::Function(Rc::new(Function::new(
+                "check",
+                vec![],
+                Block::new(vec![AST::Command(cmd("test")), AST::Command(cmd("after"))])
+                    .with_policy(FailurePolicy::Continue),
+            )))
+            .execute_with(&mut executor, &mut env)
+            .unwrap();
             executor.will_fail();
 
-            assert_eq!(while_.execute(&mut executor).unwrap(), Some(false));
+            assert!(cmd("check").execute(&mut executor, &mut env).unwrap());
+            assert_eq!(executor.count("after"), 1);
         }
 
         #[test]
-        fn one_loop_returns_last() {
+        fn piped_in_separate_processes() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+            let mut env = Env::new();
 
-            executor.will_succeed();
-            executor.will_succeed();
-            executor.will_fail();
+            define(&mut env, "greet", vec![], vec![AST::Command(cmd("hello"))]);
 
-            assert_eq!(while_.execute(&mut executor).unwrap(), Some(true));
+            assert!(Pipeline(vec![cmd("greet"), cmd("cat")])
+                .execute(&mut executor, &mut env)
+                .unwrap());
+            assert_eq!(executor.forked_pipelines, vec![vec!["greet", "cat"]]);
+            assert_eq!(executor.count("hello"), 0);
         }
 
         #[test]
-        fn error_in_predicate_breaks() {
+        fn defined_in_block_is_scoped() {
             let mut executor = TestExecutor::new();
-            let while_ = While::new(cmd("foo"), AST::Command(cmd("bar")));
+            let mut env = Env::new();
+
+            Block::new(vec![AST::Function(Rc::new(Function::new(
+                "greet",
+                vec![],
+                Block::new(vec![]),
+            )))])
+            .execute(&mut executor, &mut env)
+            .unwrap();
+
+            assert!(env.function("greet").is_none());
+        }
+    }
+
+    #[cfg(test)]
+    mod span {
+        use super::*;
+        use crate::parse::Parse;
+
+        fn at(line: usize, column: usize) -> Span {
+            Span {
+                start: 0,
+                end: 0,
+                line,
+                column,
+            }
+        }
+
+        fn undefined() -> Command {
+            Command::with_words(
+                "echo",
+                vec![Word::new(vec![WordPart::Variable("name".into())])],
+            )
+        }
+
+        #[test]
+        fn equality_ignores_spans() {
+            assert_eq!(Command::no_args("ls").at(at(3, 1)), Command::no_args("ls"));
+        }
+
+        #[test]
+        fn spans_compare_by_position() {
+            assert_eq!(at(3, 1), at(3, 1));
+            assert_ne!(at(3, 1), at(4, 1));
+        }
+
+        #[test]
+        fn statement_equality_ignores_spans() {
+            assert_eq!(
+                AST::Break(None, at(2, 5)),
+                AST::Break(None, Span::default())
+            );
+            assert_ne!(AST::Break(None, at(2, 5)), AST::Continue(None, at(2, 5)));
+        }
+
+        #[test]
+        fn error_reports_location() {
+            let command = undefined().at(at(12, 3));
+
+            let error = command
+                .execute(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "12:3: undefined variable: name");
+        }
+
+        #[test]
+        fn error_reports_file() {
+            let command = undefined().at(at(12, 3));
+
+            let error = command
+                .execute(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(
+                error.in_file("script.cm").to_string(),
+                "script.cm:12:3: undefined variable: name"
+            );
+        }
+
+        #[test]
+        fn innermost_location_wins() {
+            let block = Block::new(vec![AST::Command(undefined().at(at(2, 5)))]).at(at(1, 1));
+
+            let error = AST::Block(block)
+                .execute_with(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "2:5: undefined variable: name");
+        }
+
+        #[test]
+        fn statement_without_command_reports_its_own_location() {
+            let assign = Assignment::new("name", Word::new(vec![WordPart::Variable("x".into())]));
+
+            let error = AST::Assign(assign.at(at(4, 2)))
+                .execute_with(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "4:2: undefined variable: x");
+        }
+
+        #[test]
+        fn return_outside_function_reports_location() {
+            let ast = b"echo hi\nreturn 1\n".to_vec().parse().unwrap();
+
+            let error = Block::new(ast)
+                .run_script_with(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "2:1: return outside of a function");
+        }
+
+        #[test]
+        fn bad_return_reports_its_own_line() {
+            let ast = b"fn f() {\n  return abc\n}\nf\n".to_vec().parse().unwrap();
+
+            let error = Block::new(ast)
+                .run_script_with(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(
+                error.to_string(),
+                "2:3: return status is not an integer: abc"
+            );
+        }
+
+        #[test]
+        fn break_outside_loop_reports_location() {
+            let ast = b"\n  break\n".to_vec().parse().unwrap();
+
+            let error = Block::new(ast)
+                .run_script_with(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "2:3: break outside of a loop");
+        }
 
-            executor.will_error(failure::err_msg("err"));
+        #[test]
+        fn unknown_span_leaves_error_alone() {
+            let error = undefined()
+                .execute(&mut TestExecutor::new(), &mut Env::new())
+                .unwrap_err();
+
+            assert_eq!(error.to_string(), "undefined variable: name");
+        }
 
-            assert!(while_.execute(&mut executor).is_err());
+        #[test]
+        fn to_covers_both() {
+            let first = Span {
+                start: 0,
+                end: 4,
+                line: 1,
+                column: 1,
+            };
+            let second = Span {
+                start: 10,
+                end: 12,
+                line: 2,
+                column: 3,
+            };
+
+            let span = second.to(first);
+
+            assert_eq!((span.start, span.end, span.line), (0, 12, 1));
         }
     }
 }
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::error::Error;
+
+use crate::ast::Block;
+use crate::env::{Env, Unwind};
+use crate::executor::Success;
+use crate::parse::*;
+
+/// A command implemented inside the shell, which can change the shell's own state.
+pub type Builtin = dyn Fn(&[String], &mut Context) -> Result<Status, Error>;
+
+/// The status a builtin finishes with, like the exit code of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(i32);
+
+impl Success for Status {
+    fn success(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn from_code(code: i32) -> Self {
+        Status(code)
+    }
+
+    fn exit_code(&self) -> i32 {
+        self.0
+    }
+}
+
+/// What a builtin can work with while it runs.
+pub struct Context<'a> {
+    pub(crate) env: &'a mut Env,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+    run: &'a mut dyn FnMut(&Block, &mut Env) -> Result<Status, Error>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        env: &'a mut Env,
+        stdout: &'a mut dyn Write,
+        stderr: &'a mut dyn Write,
+        run: &'a mut dyn FnMut(&Block, &mut Env) -> Result<Status, Error>,
+    ) -> Context<'a> {
+        Context {
+            env,
+            stdout,
+            stderr,
+            run,
+        }
+    }
+
+    /// Runs `block` in the current scope, the same way the statement calling the builtin runs.
+    pub(crate) fn run(&mut self, block: &Block) -> Result<Status, Error> {
+        (self.run)(block, self.env)
+    }
+
+    /// The value of the variable `name` where the builtin was called, including exported
+    /// variables.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.env.get(name).ok()
+    }
+
+    /// Binds `name` in the scope the builtin was called from.
+    pub fn set_variable<S: ToString>(&mut self, name: &str, value: S) {
+        self.env.define(name, value);
+    }
+
+    /// Sets a variable that commands see in their environment.
+    pub fn export<S: Into<OsString>>(&mut self, name: &str, value: S) {
+        self.env.export(name, value);
+    }
+
+    /// The directory the script is running in.
+    pub fn current_dir(&self) -> &Path {
+        self.env.current_dir()
+    }
+}
+
+/// Builtins by name, each with whether it only changes the shell's own state.
+#[derive(Clone, Default)]
+pub struct Builtins(HashMap<String, (Rc<Builtin>, bool)>);
+
+impl Builtins {
+    pub fn insert(&mut self, name: &str, builtin: Rc<Builtin>, state_only: bool) {
+        self.0.insert(name.to_owned(), (builtin, state_only));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<Builtin>> {
+        self.0.get(name).map(|(builtin, _)| Rc::clone(builtin))
+    }
+
+    pub fn is_state_only(&self, name: &str) -> bool {
+        self.0.get(name).is_some_and(|&(_, state_only)| state_only)
+    }
+}
+
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Defines `cd`, `pwd`, `exit`, `export`, `unset` and `source`. All but `pwd` only change the
+/// shell's state.
+pub fn define_standard(env: &mut Env) {
+    env.define_state_builtin("cd", cd);
+    env.define_builtin("pwd", pwd);
+    env.define_state_builtin("exit", exit);
+    env.define_state_builtin("export", export);
+    env.define_state_builtin("unset", unset);
+    env.define_state_builtin("source", source);
+}
+
+fn cd(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    let dir = match args {
+        [] => context.env.get("HOME")?.to_owned(),
+        [dir] => dir.clone(),
+        _ => return Err(Error::runtime("cd: too many arguments")),
+    };
+    match context.env.set_current_dir(dir) {
+        Ok(()) => Ok(Status(0)),
+        Err(e) => {
+            writeln!(context.stderr, "{}", e)?;
+            Ok(Status(1))
+        }
+    }
+}
+
+fn pwd(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    if !args.is_empty() {
+        return Err(Error::runtime("pwd: too many arguments"));
+    }
+    writeln!(context.stdout, "{}", context.env.current_dir().display())?;
+    Ok(Status(0))
+}
+
+fn exit(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    let code = match args {
+        [] => 0,
+        [code] => code
+            .parse()
+            .map_err(|_| Error::runtime(format!("exit status is not an integer: {}", code)))?,
+        _ => return Err(Error::runtime("exit: too many arguments")),
+    };
+    context.env.unwind(Unwind::Exit);
+    Ok(Status(code))
+}
+
+/// `export NAME=value` sets and exports a variable, `export NAME` exports one that already
+/// exists, and a bare `export` lists everything exported.
+fn export(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    if args.is_empty() {
+        for (name, value) in context.env.exports() {
+            context.stdout.write_all(name.as_bytes())?;
+            context.stdout.write_all(b"=")?;
+            context.stdout.write_all(value.as_bytes())?;
+            context.stdout.write_all(b"\n")?;
+        }
+        return Ok(Status(0));
+    }
+    for arg in args {
+        match arg.find('=') {
+            Some(i) => {
+                let (name, value) = (&arg[..i], &arg[i + 1..]);
+                context.env.export(name, value);
+                context.env.assign(name, value)?;
+            }
+            None => {
+                let value = context.env.get(arg)?.to_owned();
+                context.env.export(arg, value);
+            }
+        }
+    }
+    Ok(Status(0))
+}
+
+fn unset(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    for name in args {
+        context.env.unset(name);
+    }
+    Ok(Status(0))
+}
+
+/// Runs a script in the current scope, so its variables and functions stay defined afterwards.
+fn source(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    let path = match args {
+        [path] => context.env.current_dir().join(path),
+        [] => return Err(Error::runtime("source: missing file name")),
+        _ => return Err(Error::runtime("source: too many arguments")),
+    };
+    let mut contents = Vec::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_end(&mut contents))
+        .map_err(|e| Error::runtime(format!("source: {}: {}", path.display(), e)))?;
+    let file = path.display().to_string();
+    let ast = contents.parse().map_err(|e| e.in_file(&file))?;
+    let outer = context.env.set_file(Some(file.clone()));
+    let result = context.run(&Block::new(ast));
+    context.env.set_file(outer);
+    result.map_err(|e| e.in_file(&file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    struct Output {
+        status: Status,
+        stdout: String,
+        stderr: String,
+    }
+
+    fn call(name: &str, args: &[&str], env: &mut Env) -> Result<Output, Error> {
+        let builtin = env.builtin(name).unwrap();
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut run = |_: &Block, _: &mut Env| -> Result<Status, Error> {
+            panic!("Unexpected run");
+        };
+        let status = builtin(
+            &args,
+            &mut Context::new(env, &mut stdout, &mut stderr, &mut run),
+        )?;
+        Ok(Output {
+            status,
+            stdout: String::from_utf8(stdout).unwrap(),
+            stderr: String::from_utf8(stderr).unwrap(),
+        })
+    }
+
+    #[test]
+    fn cd_changes_current_dir() {
+        let mut env = Env::new();
+
+        let output = call("cd", &["/"], &mut env).unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(env.current_dir(), Path::new("/"));
+    }
+
+    #[test]
+    fn cd_without_args_goes_home() {
+        let mut env = Env::new();
+
+        env.export("HOME", "/");
+        call("cd", &[], &mut env).unwrap();
+
+        assert_eq!(env.current_dir(), Path::new("/"));
+    }
+
+    #[test]
+    fn cd_to_missing_dir_fails() {
+        let mut env = Env::new();
+
+        let output = call("cd", &["/nonexistent"], &mut env).unwrap();
+
+        assert!(!output.status.success());
+        assert!(output.stderr.contains("/nonexistent"));
+    }
+
+    #[test]
+    fn pwd_prints_current_dir() {
+        let mut env = Env::new();
+
+        env.set_current_dir("/").unwrap();
+        let output = call("pwd", &[], &mut env).unwrap();
+
+        assert_eq!(output.stdout, "/\n");
+    }
+
+    #[test]
+    fn exit_unwinds_with_status() {
+        let mut env = Env::new();
+
+        let output = call("exit", &["3"], &mut env).unwrap();
+
+        assert_eq!(output.status, Status(3));
+        assert!(env.catch(Unwind::Exit));
+    }
+
+    #[test]
+    fn exit_without_args_succeeds() {
+        let mut env = Env::new();
+
+        assert!(call("exit", &[], &mut env).unwrap().status.success());
+    }
+
+    #[test]
+    fn exit_non_integer_is_error() {
+        let mut env = Env::new();
+
+        assert!(call("exit", &["foo"], &mut env).is_err());
+        assert!(!env.is_unwinding());
+    }
+
+    #[test]
+    fn export_with_value() {
+        let mut env = Env::new();
+
+        call("export", &["CINNAMON_TEST=foo=bar"], &mut env).unwrap();
+
+        assert_eq!(env.exports()[OsStr::new("CINNAMON_TEST")], "foo=bar");
+    }
+
+    #[test]
+    fn export_existing_variable() {
+        let mut env = Env::new();
+
+        env.define("CINNAMON_TEST", "foo");
+        call("export", &["CINNAMON_TEST"], &mut env).unwrap();
+
+        assert_eq!(env.exports()[OsStr::new("CINNAMON_TEST")], "foo");
+    }
+
+    #[test]
+    fn export_with_value_updates_variable() {
+        let mut env = Env::new();
+
+        env.define("CINNAMON_TEST", "old");
+        call("export", &["CINNAMON_TEST=new"], &mut env).unwrap();
+
+        assert_eq!(env.get("CINNAMON_TEST").unwrap(), "new");
+    }
+
+    #[test]
+    fn export_undefined_is_error() {
+        let mut env = Env::new();
+
+        assert!(call("export", &["CINNAMON_TEST"], &mut env).is_err());
+    }
+
+    #[test]
+    fn unset_removes_export() {
+        let mut env = Env::new();
+
+        env.export("CINNAMON_TEST", "foo");
+        call("unset", &["CINNAMON_TEST"], &mut env).unwrap();
+
+        assert!(env.get("CINNAMON_TEST").is_err());
+    }
+
+    #[test]
+    fn source_missing_file_is_error() {
+        let mut env = Env::new();
+
+        assert!(call("source", &["/nonexistent.cm"], &mut env).is_err());
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Error;
+
+use crate::ast::{FailurePolicy, Function, PipeStatus};
+use crate::builtins::{self, Builtin, Builtins};
+
+/// Deepest allowed nesting of function calls, so runaway recursion is an error rather than a
+/// stack overflow.
+const MAX_CALL_DEPTH: usize = 128;
+
+/// Set when Ctrl-C is pressed at the interactive shell, until it reads the next line.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Marks whatever is running as interrupted, or clears the mark. Only touches an atomic, so it is
+/// safe to call from a signal handler.
+pub fn set_interrupted(interrupted: bool) {
+    INTERRUPTED.store(interrupted, Ordering::SeqCst);
+}
+
+/// The state a script builds up while it runs.
+///
+/// Variables and functions live in a stack of scopes, one per enclosing `Block`. Lookups walk from
+/// the innermost scope outward, then fall back to the exported environment.
+#[derive(Debug, Clone)]
+pub struct Env {
+    scopes: Vec<Scope>,
+    exports: BTreeMap<OsString, OsString>,
+    script: OsString,
+    arguments: Vec<OsString>,
+    current_dir: PathBuf,
+    builtins: Builtins,
+    pipe_status: PipeStatus,
+    unwind: Option<Unwind>,
+    call_depth: usize,
+    /// The loops running, innermost last, with the call depth each runs at and its label.
+    loops: Vec<(usize, Option<String>)>,
+    failure_policy: FailurePolicy,
+    last_command: Option<String>,
+    /// The name of the script running, for warnings to say where they are.
+    file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    variables: HashMap<String, String>,
+    functions: HashMap<String, Rc<Function>>,
+}
+
+/// Why statements are being skipped until something catches the unwind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unwind {
+    Return,
+    /// `exit`, which only stops at the top level or at the edge of a subshell.
+    Exit,
+    /// `break`, for the loop with the given label or else the innermost one.
+    Break(Option<String>),
+    /// `continue`, for the loop with the given label or else the innermost one.
+    Continue(Option<String>),
+    /// Ctrl-C, which only stops at the top level of the interactive shell.
+    Interrupt,
+}
+
+impl Env {
+    /// An environment starting in this process's directory with its environment variables and
+    /// the standard builtins.
+    pub fn new() -> Env {
+        let mut env = Env {
+            scopes: vec![Scope::default()],
+            exports: std::env::vars_os().collect(),
+            script: "cinnamon".into(),
+            arguments: Vec::new(),
+            current_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+            builtins: Builtins::default(),
+            pipe_status: PipeStatus::default(),
+            unwind: None,
+            call_depth: 0,
+            loops: Vec::new(),
+            failure_policy: FailurePolicy::default(),
+            last_command: None,
+            file: None,
+        };
+        builtins::define_standard(&mut env);
+        env
+    }
+
+    pub fn pipe_status(&self) -> PipeStatus {
+        self.pipe_status
+    }
+
+    pub fn set_pipe_status(&mut self, pipe_status: PipeStatus) {
+        self.pipe_status = pipe_status;
+    }
+
+    pub fn failure_policy(&self) -> FailurePolicy {
+        self.failure_policy
+    }
+
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+    }
+
+    /// The command line that last ran, or was about to when it failed to start.
+    pub fn last_command(&self) -> Option<&str> {
+        self.last_command.as_deref()
+    }
+
+    pub fn set_last_command(&mut self, command: String) {
+        self.last_command = Some(command);
+    }
+
+    /// Forgets the last command, so a statement that fails before running one isn't blamed on
+    /// whatever ran before it.
+    pub fn clear_last_command(&mut self) {
+        self.last_command = None;
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Names the script that runs next, returning the name it replaces so it can be put back.
+    pub fn set_file(&mut self, file: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.file, file)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub fn pop_scope(&mut self) {
+        assert!(self.scopes.len() > 1, "Cannot pop the global scope");
+        self.scopes.pop();
+    }
+
+    /// Binds `name` in the innermost scope, shadowing any outer binding.
+    pub fn define<S: ToString>(&mut self, name: &str, value: S) {
+        self.scopes
+            .last_mut()
+            .expect("Env always has a global scope")
+            .variables
+            .insert(name.to_string(), value.to_string());
+    }
+
+    /// Updates the nearest existing binding of `name`, and the exported variable if there is one.
+    pub fn assign<S: ToString>(&mut self, name: &str, value: S) -> Result<(), Error> {
+        let value = value.to_string();
+        let exported = match self.exports.get_mut(OsStr::new(name)) {
+            Some(slot) => {
+                *slot = value.clone().into();
+                true
+            }
+            None => false,
+        };
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|s| s.variables.get_mut(name))
+        {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None if exported => Ok(()),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&str, Error> {
+        if let Some(value) = self.scopes.iter().rev().find_map(|s| s.variables.get(name)) {
+            return Ok(value);
+        }
+        match self.exports.get(OsStr::new(name)) {
+            Some(value) => value
+                .to_str()
+                .ok_or_else(|| Error::runtime(format!("${} is not valid UTF-8", name))),
+            None => Err(undefined(name)),
+        }
+    }
+
+    /// Sets what `$0` and the positional parameters expand to.
+    pub fn set_arguments<S: Into<OsString>>(&mut self, script: S, arguments: Vec<OsString>) {
+        self.script = script.into();
+        self.arguments = arguments;
+    }
+
+    /// `$0` is the script, and `$1` onward its arguments.
+    pub fn argument(&self, index: usize) -> Result<&OsString, Error> {
+        match index {
+            0 => Ok(&self.script),
+            _ => self.arguments.get(index - 1).ok_or(Error::MissingArgument {
+                index,
+                given: self.arguments.len(),
+            }),
+        }
+    }
+
+    pub fn arguments(&self) -> &[OsString] {
+        &self.arguments
+    }
+
+    /// Removes the nearest binding of `name` and any exported variable of that name.
+    pub fn unset(&mut self, name: &str) {
+        if let Some(scope) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|s| s.variables.contains_key(name))
+        {
+            scope.variables.remove(name);
+        }
+        self.exports.remove(OsStr::new(name));
+    }
+
+    /// Passes `name` to commands as an environment variable.
+    pub fn export<S: Into<OsString>>(&mut self, name: &str, value: S) {
+        self.exports.insert(name.into(), value.into());
+    }
+
+    /// The environment variables commands run with.
+    pub fn exports(&self) -> &BTreeMap<OsString, OsString> {
+        &self.exports
+    }
+
+    pub fn current_dir(&self) -> &Path {
+        &self.current_dir
+    }
+
+    /// Changes the directory commands run in, keeping `$PWD` in step. Relative paths are taken
+    /// from the current directory.
+    pub fn set_current_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), Error> {
+        let dir = self.current_dir.join(dir);
+        let dir = dir
+            .canonicalize()
+            .map_err(|e| Error::runtime(format!("cd: {}: {}", dir.display(), e)))?;
+        if !dir.is_dir() {
+            return Err(Error::runtime(format!(
+                "cd: not a directory: {}",
+                dir.display()
+            )));
+        }
+        self.export("PWD", &dir);
+        self.current_dir = dir;
+        Ok(())
+    }
+
+    /// Makes `builtin` run whenever a command named `name` is, unless a function shadows it.
+    pub fn define_builtin<F>(&mut self, name: &str, builtin: F)
+    where
+        F: Fn(&[String], &mut builtins::Context) -> Result<builtins::Status, Error> + 'static,
+    {
+        self.builtins.insert(name, Rc::new(builtin), false);
+    }
+
+    /// Like `define_builtin`, for a builtin like `cd` that only changes the shell's own state. It
+    /// still runs when nothing else really does, as in a dry run, since later commands depend on
+    /// it.
+    pub(crate) fn define_state_builtin<F>(&mut self, name: &str, builtin: F)
+    where
+        F: Fn(&[String], &mut builtins::Context) -> Result<builtins::Status, Error> + 'static,
+    {
+        self.builtins.insert(name, Rc::new(builtin), true);
+    }
+
+    pub fn builtin(&self, name: &str) -> Option<Rc<Builtin>> {
+        self.builtins.get(name)
+    }
+
+    /// Whether the builtin `name` was defined with `define_state_builtin`.
+    pub fn is_state_builtin(&self, name: &str) -> bool {
+        self.builtins.is_state_only(name)
+    }
+
+    /// Makes `function` callable by name from the innermost scope.
+    pub fn define_function(&mut self, function: Rc<Function>) {
+        self.scopes
+            .last_mut()
+            .expect("Env always has a global scope")
+            .functions
+            .insert(function.name().to_string(), function);
+    }
+
+    pub fn function(&self, name: &str) -> Option<Rc<Function>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.functions.get(name))
+            .cloned()
+    }
+
+    /// Records entering a function call, failing if calls are nested too deeply.
+    pub fn enter_call(&mut self) -> Result<(), Error> {
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(Error::runtime(format!(
+                "function calls nested deeper than {}",
+                MAX_CALL_DEPTH
+            )));
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+
+    pub fn leave_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    pub fn in_call(&self) -> bool {
+        self.call_depth > 0
+    }
+
+    pub fn enter_loop(&mut self, label: Option<String>) {
+        self.loops.push((self.call_depth, label));
+    }
+
+    pub fn leave_loop(&mut self) {
+        self.loops.pop();
+    }
+
+    /// Fails unless a loop labelled `label`, or any loop if `None`, is running in the current
+    /// function, since `break` and `continue` can't reach past a function call.
+    pub fn ensure_in_loop(&self, keyword: &str, label: Option<&str>) -> Result<(), Error> {
+        let mut loops = self
+            .loops
+            .iter()
+            .filter(|(depth, _)| *depth == self.call_depth);
+        match label {
+            None if loops.next().is_some() => Ok(()),
+            None => Err(Error::runtime(format!("{} outside of a loop", keyword))),
+            Some(label) if loops.any(|(_, l)| l.as_deref() == Some(label)) => Ok(()),
+            Some(label) => Err(Error::runtime(format!(
+                "{} {}: no enclosing loop has that label",
+                keyword, label
+            ))),
+        }
+    }
+
+    /// Stops a `break` or `continue` meant for the loop labelled `label`, returning it.
+    pub fn catch_loop(&mut self, label: Option<&str>) -> Option<Unwind> {
+        match &self.unwind {
+            Some(Unwind::Break(target)) | Some(Unwind::Continue(target))
+                if target.is_none() || target.as_deref() == label =>
+            {
+                self.unwind.take()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn unwind(&mut self, unwind: Unwind) {
+        self.unwind = Some(unwind);
+    }
+
+    /// Starts unwinding to the top level if Ctrl-C has been pressed.
+    pub fn check_interrupt(&mut self) {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            self.unwind = Some(Unwind::Interrupt);
+        }
+    }
+
+    pub fn is_unwinding(&self) -> bool {
+        self.unwind.is_some()
+    }
+
+    /// Stops an unwind of the given kind, returning whether one was in progress.
+    pub fn catch(&mut self, unwind: Unwind) -> bool {
+        if self.unwind == Some(unwind) {
+            self.unwind = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+fn undefined(name: &str) -> Error {
+    Error::UndefinedVariable(name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undefined_is_error() {
+        let env = Env::new();
+
+        assert!(env.get("foo").is_err());
+    }
+
+    #[test]
+    fn defined_is_value() {
+        let mut env = Env::new();
+
+        env.define("foo", "bar");
+
+        assert_eq!(env.get("foo").unwrap(), "bar");
+    }
+
+    #[test]
+    fn inner_scope_shadows() {
+        let mut env = Env::new();
+
+        env.define("foo", "outer");
+        env.push_scope();
+        env.define("foo", "inner");
+
+        assert_eq!(env.get("foo").unwrap(), "inner");
+    }
+
+    #[test]
+    fn popping_scope_drops_bindings() {
+        let mut env = Env::new();
+
+        env.push_scope();
+        env.define("foo", "bar");
+        env.pop_scope();
+
+        assert!(env.get("foo").is_err());
+    }
+
+    #[test]
+    fn assign_updates_outer_scope() {
+        let mut env = Env::new();
+
+        env.define("foo", "outer");
+        env.push_scope();
+        env.assign("foo", "updated").unwrap();
+        env.pop_scope();
+
+        assert_eq!(env.get("foo").unwrap(), "updated");
+    }
+
+    #[test]
+    fn assign_undefined_is_error() {
+        let mut env = Env::new();
+
+        assert!(env.assign("foo", "bar").is_err());
+    }
+
+    #[test]
+    fn exported_is_value() {
+        let mut env = Env::new();
+
+        env.export("CINNAMON_TEST", "bar");
+
+        assert_eq!(env.get("CINNAMON_TEST").unwrap(), "bar");
+    }
+
+    #[test]
+    fn non_utf8_export_is_kept() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut env = Env::new();
+        let value = OsString::from_vec(b"caf\xe9".to_vec());
+
+        env.export("CINNAMON_TEST", value.clone());
+
+        assert_eq!(env.exports()[OsStr::new("CINNAMON_TEST")], value);
+        assert!(env.get("CINNAMON_TEST").is_err());
+    }
+
+    #[test]
+    fn inherits_process_environment() {
+        let env = Env::new();
+
+        assert_eq!(
+            env.get("PATH").unwrap(),
+            std::env::var("PATH").unwrap().as_str()
+        );
+    }
+
+    #[test]
+    fn variable_shadows_export() {
+        let mut env = Env::new();
+
+        env.export("CINNAMON_TEST", "exported");
+        env.define("CINNAMON_TEST", "variable");
+
+        assert_eq!(env.get("CINNAMON_TEST").unwrap(), "variable");
+    }
+
+    #[test]
+    fn assign_updates_export() {
+        let mut env = Env::new();
+
+        env.export("CINNAMON_TEST", "old");
+        env.assign("CINNAMON_TEST", "new").unwrap();
+
+        assert_eq!(env.exports()[OsStr::new("CINNAMON_TEST")], "new");
+    }
+
+    #[test]
+    fn unset_removes_variable_and_export() {
+        let mut env = Env::new();
+
+        env.define("CINNAMON_TEST", "variable");
+        env.export("CINNAMON_TEST", "exported");
+        env.unset("CINNAMON_TEST");
+
+        assert!(env.get("CINNAMON_TEST").is_err());
+    }
+
+    #[test]
+    fn arguments_count_from_one() {
+        let mut env = Env::new();
+
+        env.set_arguments("deploy.cm", vec!["prod".into(), "v1.2".into()]);
+
+        assert_eq!(env.argument(0).unwrap(), "deploy.cm");
+        assert_eq!(env.argument(1).unwrap(), "prod");
+        assert_eq!(env.argument(2).unwrap(), "v1.2");
+    }
+
+    #[test]
+    fn missing_argument_is_error() {
+        let mut env = Env::new();
+
+        env.set_arguments("deploy.cm", vec!["prod".into()]);
+
+        assert!(env.argument(2).is_err());
+    }
+
+    #[test]
+    fn relative_current_dir() {
+        let mut env = Env::new();
+
+        env.set_current_dir("/").unwrap();
+        env.set_current_dir("tmp").unwrap();
+
+        assert_eq!(env.current_dir(), Path::new("/tmp").canonicalize().unwrap());
+        assert_eq!(env.get("PWD").unwrap(), env.current_dir().to_str().unwrap());
+    }
+
+    #[test]
+    fn missing_current_dir_is_error() {
+        let mut env = Env::new();
+        let before = env.current_dir().to_owned();
+
+        assert!(env.set_current_dir("/nonexistent").is_err());
+        assert_eq!(env.current_dir(), before);
+    }
+}
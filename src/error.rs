@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::ast::Span;
+use crate::parse::ParseErrors;
+
+/// Everything that can stop a script from parsing or running.
+#[derive(Debug)]
+pub enum Error {
+    /// The script is not valid cinnamon.
+    Syntax(ParseErrors),
+    /// A command could not be started.
+    Spawn {
+        command: String,
+        error: io::Error,
+    },
+    /// A file named in a redirection could not be opened.
+    Redirect {
+        path: PathBuf,
+        /// `"reading"` or `"writing"`.
+        purpose: &'static str,
+        error: io::Error,
+    },
+    /// A file the shell itself reads or writes, like a script, could not be opened.
+    Open {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UndefinedVariable(String),
+    /// `$n` past the end of the script's arguments.
+    MissingArgument {
+        index: usize,
+        given: usize,
+    },
+    /// The script asked for something that can't be done, like `return` outside of a function.
+    Runtime(String),
+    Io(io::Error),
+    /// Something that should never happen, like an executor breaking its contract.
+    Internal(String),
+    /// An error raised by the statement at `span`, in `file` when the script came from one.
+    Located {
+        file: Option<String>,
+        span: Span,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn runtime<S: Into<String>>(message: S) -> Error {
+        Error::Runtime(message.into())
+    }
+
+    /// The error itself, without the statement it was raised by.
+    pub fn unlocated(&self) -> &Error {
+        match self {
+            Error::Located { error, .. } => error.unlocated(),
+            error => error,
+        }
+    }
+
+    /// Marks the error as coming from the statement at `span`, unless a statement inside it
+    /// already claimed it.
+    pub(crate) fn at(self, span: Span) -> Error {
+        match self {
+            Error::Located { .. } => self,
+            _ if !span.is_known() => self,
+            error => Error::Located {
+                file: None,
+                span,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Names the file the script that raised the error came from, unless it already has one, as
+    /// for an error in a script run with `source`.
+    pub(crate) fn in_file(self, name: &str) -> Error {
+        match self {
+            Error::Located {
+                file: None,
+                span,
+                error,
+            } => Error::Located {
+                file: Some(name.to_owned()),
+                span,
+                error,
+            },
+            Error::Syntax(errors) => Error::Syntax(errors.in_file(name)),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax(errors) => write!(f, "{}", errors),
+            Error::Spawn { command, error } if error.kind() == io::ErrorKind::NotFound => {
+                write!(f, "command not found: {}", command)
+            }
+            Error::Spawn { command, error } => write!(f, "{}: {}", command, error),
+            Error::Redirect {
+                path,
+                purpose,
+                error,
+            } => write!(
+                f,
+                "cannot open {} for {}: {}",
+                path.display(),
+                purpose,
+                error
+            ),
+            Error::Open { path, source } => write!(f, "cannot open {}: {}", path.display(), source),
+            Error::UndefinedVariable(name) => write!(f, "undefined variable: {}", name),
+            Error::MissingArgument { index, given } => write!(
+                f,
+                "missing argument ${}: the script was given {}",
+                index, given
+            ),
+            Error::Runtime(message) => write!(f, "{}", message),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Internal(message) => write!(f, "internal error: {}", message),
+            Error::Located { file, span, error } => {
+                if let Some(file) = file {
+                    write!(f, "{}:", file)?;
+                }
+                write!(f, "{}: {}", span, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Syntax(errors) => Some(errors),
+            Error::Spawn { error, .. } | Error::Redirect { error, .. } | Error::Io(error) => {
+                Some(error)
+            }
+            Error::Open { source, .. } => Some(source),
+            Error::Located { error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<ParseErrors> for Error {
+    fn from(errors: ParseErrors) -> Error {
+        Error::Syntax(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span() -> Span {
+        Span {
+            start: 4,
+            end: 8,
+            line: 2,
+            column: 3,
+        }
+    }
+
+    #[test]
+    fn located_in_file() {
+        let error = Error::UndefinedVariable("x".into())
+            .at(span())
+            .in_file("a.cm");
+
+        assert_eq!(error.to_string(), "a.cm:2:3: undefined variable: x");
+    }
+
+    #[test]
+    fn innermost_location_wins() {
+        let error = Error::runtime("no")
+            .at(span())
+            .at(Span { line: 1, ..span() });
+
+        assert_eq!(error.to_string(), "2:3: no");
+    }
+
+    #[test]
+    fn unknown_span_leaves_error_alone() {
+        let error = Error::runtime("no").at(Span::default());
+
+        assert!(matches!(error, Error::Runtime(_)));
+    }
+
+    #[test]
+    fn first_file_wins() {
+        let error = Error::runtime("no")
+            .at(span())
+            .in_file("inner.cm")
+            .in_file("outer.cm");
+
+        assert_eq!(error.to_string(), "inner.cm:2:3: no");
+    }
+
+    #[test]
+    fn unlocated() {
+        let error = Error::MissingArgument { index: 2, given: 1 }.at(span());
+
+        assert!(matches!(
+            error.unlocated(),
+            Error::MissingArgument { index: 2, given: 1 }
+        ));
+    }
+
+    #[test]
+    fn spawn_not_found() {
+        let error = Error::Spawn {
+            command: "nope".into(),
+            error: io::ErrorKind::NotFound.into(),
+        };
+
+        assert_eq!(error.to_string(), "command not found: nope");
+    }
+}
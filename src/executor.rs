@@ -0,0 +1,1056 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command as ProcessCommand, ExitStatus};
+use std::thread;
+use std::time::Instant;
+
+use crate::error::Error;
+
+use crate::ast::{Fd, Redirect};
+
+/// A builtin or function as an executor runs it, given the executor and the streams to use, if
+/// any.
+pub type Internal<'a, E> =
+    dyn FnMut(&mut E, Option<Streams>) -> Result<<E as Executor>::ExitStatus, Error> + 'a;
+
+pub trait Executor {
+    type ExitStatus: Success;
+
+    fn execute(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error>;
+
+    /// Runs a command whose status decides a condition, as with `if`, `while`, `!`, `&&` and
+    /// `||`. The same as `execute` unless the executor treats conditions specially.
+    fn condition(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        self.execute(invocation)
+    }
+
+    /// Like `execute`, but returns everything the command wrote to stdout instead of letting it
+    /// through.
+    fn capture(&mut self, invocation: &Invocation) -> Result<(Self::ExitStatus, String), Error>;
+
+    /// Runs every stage concurrently with each stage's stdout connected to the next stage's stdin.
+    /// Returns the statuses in stage order.
+    fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Self::ExitStatus>, Error>;
+
+    /// Runs `stages`, a pipeline with builtins or functions in it, which can only run in a copy
+    /// of the shell. An executor that really runs them starts a process for each stage and, like
+    /// `fork(2)`, returns more than once: `Forked::Child` in each new process, and
+    /// `Forked::Parent` in this one once they have all exited. One that doesn't can return
+    /// `Forked::Parent` straight away.
+    fn fork_pipeline(&mut self, stages: &[Invocation]) -> Result<Forked<Self::ExitStatus>, Error>;
+
+    /// Runs `child` in a separate process and returns the status it produced there. Nothing
+    /// `child` does can affect this process.
+    fn fork(
+        &mut self,
+        child: &mut dyn FnMut(&mut Self) -> Result<Self::ExitStatus, Error>,
+    ) -> Result<Self::ExitStatus, Error>;
+
+    /// Runs a builtin or function in this process as the command `invocation`, so it can change
+    /// the shell's own state. `run` is handed the streams to use in place of this process's own:
+    /// `inherited` with the invocation's redirections applied, or `None` if nothing really runs.
+    /// Then only functions and the builtins that just change the shell's state, like `cd`, run,
+    /// writing nowhere.
+    fn internal(
+        &mut self,
+        invocation: &Invocation,
+        inherited: Streams,
+        run: &mut Internal<'_, Self>,
+    ) -> Result<Self::ExitStatus, Error>;
+
+    /// Called as the script starts running the body of `scope`, like a branch of an `if` or an
+    /// iteration of a loop, for executors that report where each command ran.
+    fn enter(&mut self, _scope: &dyn fmt::Display) {}
+
+    /// Called as the script finishes the scope it last entered.
+    fn leave(&mut self) {}
+}
+
+/// Where `Executor::fork_pipeline` returns to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Forked<S> {
+    /// This process, once every stage has exited, with their statuses in stage order.
+    Parent(Vec<S>),
+    /// The process started for the stage with this index, with its stdin and stdout already
+    /// connected to its neighbours. The caller runs the stage there, then exits.
+    Child(usize),
+}
+
+pub trait Success {
+    fn success(&self) -> bool;
+
+    /// The status of a process that exited with `code`, used for statuses the script produces
+    /// itself, like `return 1;`.
+    fn from_code(code: i32) -> Self;
+
+    /// The exit code a process reports for this status, with signals reported as `128 + signal`.
+    fn exit_code(&self) -> i32;
+}
+
+impl Success for ExitStatus {
+    fn success(&self) -> bool {
+        self.success()
+    }
+
+    fn from_code(code: i32) -> Self {
+        ExitStatus::from_raw((code & 0xff) << 8)
+    }
+
+    fn exit_code(&self) -> i32 {
+        self.code()
+            .or_else(|| self.signal().map(|s| 128 + s))
+            .unwrap_or(1)
+    }
+}
+
+/// A command with its arguments and redirections expanded, ready to be spawned.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Invocation<'a> {
+    pub command: Cow<'a, OsStr>,
+    pub args: Vec<OsString>,
+    pub redirects: Vec<Redirect<OsString>>,
+    /// The directory to run in, which relative redirect paths are also taken from.
+    pub dir: PathBuf,
+    /// The complete environment to run with.
+    pub env: BTreeMap<OsString, OsString>,
+}
+
+/// Shows the command as a line of shell, quoting each word that needs it.
+impl<'a> fmt::Display for Invocation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", quote(&self.command))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        for redirect in &self.redirects {
+            match redirect {
+                Redirect::Read(path) => write!(f, " < {}", quote(path))?,
+                Redirect::Write { fd, path, append } => {
+                    let fd = match fd {
+                        Fd::Stdout => "",
+                        Fd::Stderr => "2",
+                    };
+                    let op = if *append { ">>" } else { ">" };
+                    write!(f, " {}{} {}", fd, op, quote(path))?;
+                }
+                Redirect::StderrToStdout => write!(f, " 2>&1")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `word` as a shell would need it written to read it back unchanged: as it is if that's safe,
+/// otherwise in single quotes, or as `$'...'` with escapes if it isn't UTF-8.
+fn quote(word: &OsStr) -> Cow<'_, str> {
+    let bytes = word.as_bytes();
+    let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_-+=:,./@%".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(safe) {
+        return String::from_utf8_lossy(bytes);
+    }
+    match word.to_str() {
+        Some(text) => format!("'{}'", text.replace('\'', "'\\''")).into(),
+        None => {
+            let mut quoted = String::from("$'");
+            for &b in bytes {
+                match b {
+                    b'\'' | b'\\' => {
+                        quoted.push('\\');
+                        quoted.push(b as char);
+                    }
+                    b' '..=b'~' => quoted.push(b as char),
+                    _ => quoted.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+            quoted.push('\'');
+            quoted.into()
+        }
+    }
+}
+
+pub struct StdExecutor;
+
+impl Executor for StdExecutor {
+    type ExitStatus = ExitStatus;
+
+    fn execute(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        let exit = spawn(invocation, None, None)?.wait()?;
+        Ok(exit)
+    }
+
+    fn capture(&mut self, invocation: &Invocation) -> Result<(Self::ExitStatus, String), Error> {
+        let (mut reader, writer) = io::pipe()?;
+        let mut child = spawn(invocation, None, Some(pipe_file(writer)))?;
+
+        let mut stdout = Vec::new();
+        let read = reader.read_to_end(&mut stdout);
+        let status = child.wait()?;
+        read?;
+
+        let stdout = String::from_utf8(stdout).map_err(|_| {
+            Error::runtime(format!(
+                "{} produced output that is not UTF-8",
+                invocation.command.to_string_lossy()
+            ))
+        })?;
+        Ok((status, stdout))
+    }
+
+    fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Self::ExitStatus>, Error> {
+        let mut children: Vec<Child> = Vec::with_capacity(stages.len());
+        let mut stdin = None;
+
+        for (i, stage) in stages.iter().enumerate() {
+            let (next_stdin, stdout) = if i + 1 == stages.len() {
+                (None, None)
+            } else {
+                match io::pipe() {
+                    Ok((reader, writer)) => (Some(pipe_file(reader)), Some(pipe_file(writer))),
+                    Err(e) => return Err(reap(children, e.into())),
+                }
+            };
+            match spawn(stage, stdin.take(), stdout) {
+                Ok(child) => children.push(child),
+                // Earlier stages see a closed pipe once we return, but still need reaping.
+                Err(e) => return Err(reap(children, e)),
+            }
+            stdin = next_stdin;
+        }
+
+        children
+            .into_iter()
+            .map(|mut child| Ok(child.wait()?))
+            .collect()
+    }
+
+    fn fork_pipeline(&mut self, stages: &[Invocation]) -> Result<Forked<Self::ExitStatus>, Error> {
+        fork_stages(stages.len())
+    }
+
+    fn fork(
+        &mut self,
+        child: &mut dyn FnMut(&mut Self) -> Result<Self::ExitStatus, Error>,
+    ) -> Result<Self::ExitStatus, Error> {
+        fork_process(self, child)
+    }
+
+    fn internal(
+        &mut self,
+        invocation: &Invocation,
+        inherited: Streams,
+        run: &mut Internal<'_, Self>,
+    ) -> Result<Self::ExitStatus, Error> {
+        let streams = inherited.redirect(&invocation.redirects, &invocation.dir)?;
+        run(self, Some(streams))
+    }
+}
+
+/// Runs `child` in a forked copy of this process, waiting for it to exit.
+fn fork_process<E: Executor>(
+    executor: &mut E,
+    child: &mut dyn FnMut(&mut E) -> Result<E::ExitStatus, Error>,
+) -> Result<ExitStatus, Error> {
+    // Anything buffered now would otherwise be written by both processes.
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => exit_child(child(executor)),
+        pid => wait_for(pid),
+    }
+}
+
+/// Forks a process for each of `count` pipeline stages, each with its stdout connected to the
+/// next one's stdin, as `Executor::fork_pipeline` describes.
+fn fork_stages<S: Success>(count: usize) -> Result<Forked<S>, Error> {
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+
+    let mut pids = Vec::with_capacity(count);
+    let mut stdin = None;
+    for i in 0..count {
+        let (next_stdin, stdout) = if i + 1 == count {
+            (None, None)
+        } else {
+            match io::pipe() {
+                Ok((reader, writer)) => (Some(pipe_file(reader)), Some(pipe_file(writer))),
+                Err(e) => return Err(reap_forked(pids, e.into())),
+            }
+        };
+        match unsafe { libc::fork() } {
+            -1 => return Err(reap_forked(pids, io::Error::last_os_error().into())),
+            0 => {
+                // Holding on to the next stage's end would keep it from ever seeing the pipe close.
+                drop(next_stdin);
+                let connected = replace_fd(stdin.take(), libc::STDIN_FILENO)
+                    .and_then(|_| replace_fd(stdout, libc::STDOUT_FILENO));
+                if let Err(e) = connected {
+                    exit_child::<S>(Err(e.into()));
+                }
+                return Ok(Forked::Child(i));
+            }
+            pid => pids.push(pid),
+        }
+        stdin = next_stdin;
+    }
+
+    let statuses = pids
+        .into_iter()
+        .map(|pid| Ok(S::from_code(wait_for(pid)?.exit_code())))
+        .collect::<Result<_, Error>>()?;
+    Ok(Forked::Parent(statuses))
+}
+
+/// Ends a forked process with the exit code for `result`, reporting it first if it's an error.
+pub(crate) fn exit_child<S: Success>(result: Result<S, Error>) -> ! {
+    let code = match result {
+        Ok(status) => status.exit_code(),
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    unsafe { libc::_exit(code) }
+}
+
+fn wait_for(pid: libc::pid_t) -> Result<ExitStatus, Error> {
+    let mut status = 0;
+    if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(ExitStatus::from_raw(status))
+}
+
+fn reap_forked(pids: Vec<libc::pid_t>, error: Error) -> Error {
+    for pid in pids {
+        let _ = wait_for(pid);
+    }
+    error
+}
+
+/// Makes `file`, if there is one, this process's descriptor `fd` in place of what that was.
+fn replace_fd(file: Option<File>, fd: RawFd) -> io::Result<()> {
+    match file {
+        Some(file) if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 => {
+            Err(io::Error::last_os_error())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Prints each command instead of running it, as a line of shell that would run it, and says
+/// every command succeeded. Conditions get the assumed status instead, so each path through `if`
+/// and `while` can be previewed. Commands whose output is captured appear to print nothing.
+pub struct DryRunExecutor<W> {
+    out: W,
+    assumed: ExitStatus,
+}
+
+impl DryRunExecutor<io::Stdout> {
+    pub fn new(assumed_status: i32) -> Self {
+        DryRunExecutor::with_output(io::stdout(), assumed_status)
+    }
+}
+
+impl<W: Write> DryRunExecutor<W> {
+    /// Prints to `out` rather than stdout.
+    pub fn with_output(out: W, assumed_status: i32) -> Self {
+        DryRunExecutor {
+            out,
+            assumed: ExitStatus::from_code(assumed_status),
+        }
+    }
+
+    /// Where the commands were printed.
+    pub fn output(&self) -> &W {
+        &self.out
+    }
+}
+
+impl<W: Write> Executor for DryRunExecutor<W> {
+    type ExitStatus = ExitStatus;
+
+    fn execute(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        writeln!(self.out, "{}", invocation)?;
+        Ok(ExitStatus::from_code(0))
+    }
+
+    fn condition(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        self.execute(invocation)?;
+        Ok(self.assumed)
+    }
+
+    fn capture(&mut self, invocation: &Invocation) -> Result<(Self::ExitStatus, String), Error> {
+        Ok((self.execute(invocation)?, String::new()))
+    }
+
+    fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Self::ExitStatus>, Error> {
+        let line: Vec<_> = stages.iter().map(ToString::to_string).collect();
+        writeln!(self.out, "{}", line.join(" | "))?;
+        Ok(vec![ExitStatus::from_code(0); stages.len()])
+    }
+
+    /// Prints the pipeline like any other, without starting anything.
+    fn fork_pipeline(&mut self, stages: &[Invocation]) -> Result<Forked<Self::ExitStatus>, Error> {
+        self.pipeline(stages).map(Forked::Parent)
+    }
+
+    /// Nothing runs, so there's nothing to keep apart from this process.
+    fn fork(
+        &mut self,
+        child: &mut dyn FnMut(&mut Self) -> Result<Self::ExitStatus, Error>,
+    ) -> Result<Self::ExitStatus, Error> {
+        child(self)
+    }
+
+    /// Prints the command without opening the files it redirects to. Functions and builtins like
+    /// `cd` and `export` still run, writing nowhere, since what they change affects later
+    /// commands. Other builtins, including ones an embedder defines, only print.
+    fn internal(
+        &mut self,
+        invocation: &Invocation,
+        _inherited: Streams,
+        run: &mut Internal<'_, Self>,
+    ) -> Result<Self::ExitStatus, Error> {
+        writeln!(self.out, "{}", invocation)?;
+        run(self, None)
+    }
+}
+
+/// Runs every command with another executor, writing each one to `out` as it starts, indented by
+/// the blocks and loops it runs in, followed by its status and how long it took.
+pub struct TraceExecutor<E, W> {
+    inner: E,
+    out: W,
+    depth: usize,
+}
+
+impl<E: Executor, W: Write> TraceExecutor<E, W> {
+    pub fn new(inner: E, out: W) -> Self {
+        TraceExecutor {
+            inner,
+            out,
+            depth: 0,
+        }
+    }
+
+    fn trace<T>(
+        &mut self,
+        line: &dyn fmt::Display,
+        run: impl FnOnce(&mut Self) -> Result<T, Error>,
+        statuses: impl FnOnce(&T) -> String,
+    ) -> Result<T, Error> {
+        let start = self.begin(line)?;
+        let result = run(self);
+        self.end(start, result.as_ref().map(statuses))?;
+        result
+    }
+
+    /// Writes `line` as a command that's starting, returning when it started.
+    fn begin(&mut self, line: &dyn fmt::Display) -> Result<Instant, Error> {
+        writeln!(self.out, "{}+ {}", "  ".repeat(self.depth), line)?;
+        self.out.flush()?;
+        Ok(Instant::now())
+    }
+
+    /// Writes how the command that started at `start` finished: with `statuses`, or an error.
+    fn end(&mut self, start: Instant, statuses: Result<String, &Error>) -> Result<(), Error> {
+        let indent = "  ".repeat(self.depth);
+        let elapsed = start.elapsed();
+        match statuses {
+            Ok(statuses) => writeln!(self.out, "{}=> {} ({:.1?})", indent, statuses, elapsed)?,
+            Err(e) => writeln!(self.out, "{}=> failed: {} ({:.1?})", indent, e, elapsed)?,
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<E: Executor, W: Write> Executor for TraceExecutor<E, W> {
+    type ExitStatus = E::ExitStatus;
+
+    fn execute(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        self.trace(invocation, |t| t.inner.execute(invocation), status)
+    }
+
+    fn condition(&mut self, invocation: &Invocation) -> Result<Self::ExitStatus, Error> {
+        self.trace(invocation, |t| t.inner.condition(invocation), status)
+    }
+
+    fn capture(&mut self, invocation: &Invocation) -> Result<(Self::ExitStatus, String), Error> {
+        let line = format!("$({})", invocation);
+        self.trace(&line, |t| t.inner.capture(invocation), |(s, _)| status(s))
+    }
+
+    fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Self::ExitStatus>, Error> {
+        let line: Vec<_> = stages.iter().map(ToString::to_string).collect();
+        self.trace(
+            &line.join(" | "),
+            |t| t.inner.pipeline(stages),
+            |s| statuses(s),
+        )
+    }
+
+    /// Only this process reports how the pipeline finished. Each stage's process goes on to trace
+    /// what the stage runs.
+    fn fork_pipeline(&mut self, stages: &[Invocation]) -> Result<Forked<Self::ExitStatus>, Error> {
+        let line: Vec<_> = stages.iter().map(ToString::to_string).collect();
+        let start = self.begin(&line.join(" | "))?;
+        let result = self.inner.fork_pipeline(stages);
+        match &result {
+            Ok(Forked::Parent(s)) => self.end(start, Ok(statuses(s)))?,
+            Ok(Forked::Child(_)) => {}
+            Err(e) => self.end(start, Err(e))?,
+        }
+        result
+    }
+
+    /// Forks a real process whatever the inner executor would do, since the child has to be
+    /// traced as well.
+    fn fork(
+        &mut self,
+        child: &mut dyn FnMut(&mut Self) -> Result<Self::ExitStatus, Error>,
+    ) -> Result<Self::ExitStatus, Error> {
+        self.out.flush()?;
+        let status = fork_process(self, child)?;
+        Ok(E::ExitStatus::from_code(status.exit_code()))
+    }
+
+    /// Lets the inner executor decide on the streams, but runs the command itself, since what it
+    /// runs in turn, like a function body or a script given to `source`, has to be traced as well.
+    /// That's indented beneath it.
+    fn internal(
+        &mut self,
+        invocation: &Invocation,
+        inherited: Streams,
+        run: &mut Internal<'_, Self>,
+    ) -> Result<Self::ExitStatus, Error> {
+        let run = |trace: &mut Self| {
+            let mut opened = None;
+            let status = trace
+                .inner
+                .internal(invocation, inherited, &mut |_, streams| {
+                    opened = Some(streams);
+                    Ok(E::ExitStatus::from_code(0))
+                })?;
+            let streams = match opened {
+                Some(streams) => streams,
+                None => return Ok(status),
+            };
+            trace.depth += 1;
+            let status = run(trace, streams);
+            trace.depth -= 1;
+            status
+        };
+        self.trace(invocation, run, status)
+    }
+
+    fn enter(&mut self, scope: &dyn fmt::Display) {
+        let _ = writeln!(self.out, "{}{}", "  ".repeat(self.depth), scope);
+        self.depth += 1;
+        self.inner.enter(scope);
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+        self.inner.leave();
+    }
+}
+
+fn status<S: Success>(status: &S) -> String {
+    status.exit_code().to_string()
+}
+
+fn statuses<S: Success>(statuses: &[S]) -> String {
+    let codes: Vec<_> = statuses.iter().map(status).collect();
+    codes.join(" | ")
+}
+
+fn reap(children: Vec<Child>, error: Error) -> Error {
+    for mut child in children {
+        let _ = child.wait();
+    }
+    error
+}
+
+fn pipe_file<F: Into<OwnedFd>>(end: F) -> File {
+    File::from(end.into())
+}
+
+/// Calls `run` with the write end of a pipe, returning what it returned along with everything
+/// written to the pipe before `run` and every copy of the write end were done with it.
+pub(crate) fn collect_output<T>(
+    run: impl FnOnce(File) -> Result<T, Error>,
+) -> Result<(T, Vec<u8>), Error> {
+    let (mut reader, writer) = io::pipe()?;
+    let reading = thread::spawn(move || {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).map(|_| output)
+    });
+    let result = run(pipe_file(writer));
+    let output = reading
+        .join()
+        .map_err(|_| Error::Internal("reading output panicked".to_owned()))??;
+    Ok((result?, output))
+}
+
+/// Where a command's standard streams go, with `None` meaning the stream is inherited.
+#[derive(Debug, Default)]
+pub struct Streams {
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+}
+
+impl Streams {
+    /// Applies `redirects` left to right, so `> out 2>&1` sends both streams to `out` while
+    /// `2>&1 > out` sends stderr wherever stdout pointed beforehand. Relative paths are taken
+    /// from `dir`.
+    pub fn redirect(
+        mut self,
+        redirects: &[Redirect<OsString>],
+        dir: &Path,
+    ) -> Result<Streams, Error> {
+        for redirect in redirects {
+            match redirect {
+                Redirect::Read(path) => {
+                    let file =
+                        File::open(dir.join(path)).map_err(|e| open_error(path, "reading", e))?;
+                    self.stdin = Some(file);
+                }
+                Redirect::Write { fd, path, append } => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .append(*append)
+                        .truncate(!*append)
+                        .open(dir.join(path))
+                        .map_err(|e| open_error(path, "writing", e))?;
+                    match fd {
+                        Fd::Stdout => self.stdout = Some(file),
+                        Fd::Stderr => self.stderr = Some(file),
+                    }
+                }
+                Redirect::StderrToStdout => {
+                    let target = match &self.stdout {
+                        Some(file) => file.try_clone()?,
+                        None => File::from(io::stdout().as_fd().try_clone_to_owned()?),
+                    };
+                    self.stderr = Some(target);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Points this process's own standard streams at these until the returned guard is dropped,
+    /// so the commands a function runs inherit them.
+    pub(crate) fn install(self) -> Result<Installed, Error> {
+        io::stdout().flush()?;
+        let mut installed = Installed { saved: Vec::new() };
+        let streams = [
+            (self.stdin, libc::STDIN_FILENO),
+            (self.stdout, libc::STDOUT_FILENO),
+            (self.stderr, libc::STDERR_FILENO),
+        ];
+        for (file, fd) in streams {
+            if file.is_some() {
+                let original = unsafe { BorrowedFd::borrow_raw(fd) };
+                installed.saved.push((fd, original.try_clone_to_owned()?));
+                replace_fd(file, fd)?;
+            }
+        }
+        Ok(installed)
+    }
+}
+
+/// This process's standard streams as they were before `Streams::install`, put back on drop.
+pub(crate) struct Installed {
+    saved: Vec<(RawFd, OwnedFd)>,
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        for (fd, original) in self.saved.drain(..).rev() {
+            unsafe { libc::dup2(original.as_raw_fd(), fd) };
+        }
+    }
+}
+
+/// Spawns `invocation` with the given default stdin and stdout, which are inherited when `None`.
+fn spawn(
+    invocation: &Invocation,
+    stdin: Option<File>,
+    stdout: Option<File>,
+) -> Result<Child, Error> {
+    let streams = Streams {
+        stdin,
+        stdout,
+        stderr: None,
+    }
+    .redirect(&invocation.redirects, &invocation.dir)?;
+
+    let mut process = ProcessCommand::new(&*invocation.command);
+    process
+        .args(&invocation.args)
+        .current_dir(&invocation.dir)
+        .env_clear()
+        .envs(&invocation.env);
+    if let Some(stdin) = streams.stdin {
+        process.stdin(stdin);
+    }
+    if let Some(stdout) = streams.stdout {
+        process.stdout(stdout);
+    }
+    if let Some(stderr) = streams.stderr {
+        process.stderr(stderr);
+    }
+    process.spawn().map_err(|error| Error::Spawn {
+        command: invocation.command.to_string_lossy().into_owned(),
+        error,
+    })
+}
+
+fn open_error(path: &OsStr, purpose: &'static str, error: io::Error) -> Error {
+    Error::Redirect {
+        path: PathBuf::from(path),
+        purpose,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_of_exited_process() {
+        assert_eq!(ExitStatus::from_code(3).exit_code(), 3);
+    }
+
+    #[test]
+    fn exit_code_of_signalled_process() {
+        assert_eq!(ExitStatus::from_raw(libc::SIGINT).exit_code(), 130);
+    }
+
+    #[test]
+    fn passes_non_utf8_args() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = std::env::temp_dir();
+        let name = OsStr::from_bytes(b"cinnamon-caf\xe9").to_owned();
+        let path = dir.join(format!("{}", std::process::id())).join(&name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let status = StdExecutor
+            .execute(&Invocation {
+                command: OsStr::new("touch").into(),
+                args: vec![path.clone().into_os_string()],
+                redirects: vec![],
+                dir,
+                env: std::env::vars_os().collect(),
+            })
+            .unwrap();
+        let created = path.exists();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert!(status.success());
+        assert!(created);
+    }
+
+    fn invocation(command: &str, args: &[&str]) -> Invocation<'static> {
+        Invocation {
+            command: OsString::from(command).into(),
+            args: args.iter().map(OsString::from).collect(),
+            redirects: vec![],
+            dir: std::env::temp_dir(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn display_quotes_words_that_need_it() {
+        let invocation = invocation("rm", &["-rf", "my files", "it's", "", "a/b.txt"]);
+
+        assert_eq!(
+            invocation.to_string(),
+            "rm -rf 'my files' 'it'\\''s' '' a/b.txt"
+        );
+    }
+
+    #[test]
+    fn display_non_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut invocation = invocation("touch", &[]);
+        invocation
+            .args
+            .push(OsString::from_vec(b"caf\xe9 'x'".to_vec()));
+
+        assert_eq!(invocation.to_string(), "touch $'caf\\xe9 \\'x\\''");
+    }
+
+    #[test]
+    fn display_redirects() {
+        let mut invocation = invocation("sort", &[]);
+        invocation.redirects = vec![
+            Redirect::Read("in put".into()),
+            Redirect::Write {
+                fd: Fd::Stdout,
+                path: "out".into(),
+                append: false,
+            },
+            Redirect::Write {
+                fd: Fd::Stderr,
+                path: "log".into(),
+                append: true,
+            },
+            Redirect::StderrToStdout,
+        ];
+
+        assert_eq!(invocation.to_string(), "sort < 'in put' > out 2>> log 2>&1");
+    }
+
+    #[cfg(test)]
+    mod dry_run {
+        use super::*;
+        use crate::ast::Block;
+        use crate::builtins::Status;
+        use crate::env::Env;
+        use crate::parse::Parse;
+
+        fn dry_run(script: &str, assumed_status: i32) -> (i32, String) {
+            let mut out = Vec::new();
+            let ast = script.as_bytes().to_vec().parse().unwrap();
+            let code = Block::new(ast)
+                .run_script_with(
+                    &mut DryRunExecutor::with_output(&mut out, assumed_status),
+                    &mut Env::new(),
+                )
+                .unwrap();
+            (code, String::from_utf8(out).unwrap())
+        }
+
+        #[test]
+        fn prints_instead_of_running() {
+            let (code, out) = dry_run("rm -rf \"/tmp/cinnamon dry run\"\nls | wc -l\n", 0);
+
+            assert_eq!(code, 0);
+            assert_eq!(out, "rm -rf '/tmp/cinnamon dry run'\nls | wc -l\n");
+        }
+
+        #[test]
+        fn conditions_get_assumed_status() {
+            let script = "if test -f x { echo yes } else { echo no }\ntrue && echo and\n";
+
+            assert_eq!(
+                dry_run(script, 0).1,
+                "test -f x\necho yes\ntrue\necho and\n"
+            );
+            assert_eq!(dry_run(script, 1).1, "test -f x\necho no\ntrue\n");
+        }
+
+        #[test]
+        fn other_commands_succeed() {
+            let (code, out) = dry_run("false\necho after\n", 1);
+
+            assert_eq!(code, 0);
+            assert_eq!(out, "false\necho after\n");
+        }
+
+        #[test]
+        fn substitutions_are_empty() {
+            assert_eq!(dry_run("echo x$(date)x\n", 0).1, "date\necho xx\n");
+        }
+
+        #[test]
+        fn builtins_leave_redirect_targets_alone() {
+            let path = std::env::temp_dir().join(format!("cinnamon-dry-{}", std::process::id()));
+            std::fs::write(&path, "keep").unwrap();
+
+            let script = format!("pwd > {}\ncd /\necho $(pwd)\n", path.display());
+            let (_, out) = dry_run(&script, 0);
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(
+                out,
+                format!("pwd > {}\ncd /\npwd\necho ''\n", path.display())
+            );
+            assert_eq!(contents, "keep");
+        }
+
+        #[test]
+        fn only_state_builtins_run() {
+            let mut out = Vec::new();
+            let mut env = Env::new();
+            env.define_builtin("launch", |_, context| {
+                context.set_variable("launched", "yes");
+                Ok(Status::from_code(0))
+            });
+            let ast = b"launch\nexport CINNAMON_DRY=1\n".to_vec().parse().unwrap();
+
+            Block::new(ast)
+                .run_script_with(&mut DryRunExecutor::with_output(&mut out, 0), &mut env)
+                .unwrap();
+
+            assert_eq!(out, b"launch\nexport CINNAMON_DRY=1\n");
+            assert!(env.get("launched").is_err());
+            assert_eq!(env.get("CINNAMON_DRY").unwrap(), "1");
+        }
+
+        #[test]
+        fn functions_leave_redirect_targets_alone() {
+            let path = std::env::temp_dir().join(format!("cinnamon-dry-fn-{}", std::process::id()));
+            std::fs::write(&path, "keep").unwrap();
+
+            let script = format!("fn f() {{ echo hi; }}\nf > {}\n", path.display());
+            let (_, out) = dry_run(&script, 0);
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(out, format!("f > {}\necho hi\n", path.display()));
+            assert_eq!(contents, "keep");
+        }
+    }
+
+    #[cfg(test)]
+    mod trace {
+        use super::*;
+        use crate::ast::Block;
+        use crate::env::Env;
+        use crate::parse::Parse;
+
+        /// The trace of a dry run, without the timings, which change from run to run.
+        fn trace(script: &str, assumed_status: i32) -> String {
+            let mut out = Vec::new();
+            let ast = script.as_bytes().to_vec().parse().unwrap();
+            let inner = DryRunExecutor::with_output(io::sink(), assumed_status);
+            Block::new(ast)
+                .run_script_with(&mut TraceExecutor::new(inner, &mut out), &mut Env::new())
+                .unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(|line| match line.rfind(" (") {
+                    Some(i) if line.trim_start().starts_with("=>") => &line[..i],
+                    _ => line,
+                })
+                .map(|line| format!("{}\n", line))
+                .collect()
+        }
+
+        #[test]
+        fn commands_with_statuses() {
+            assert_eq!(
+                trace("echo \"a b\"\nls | wc -l\n", 0),
+                "+ echo 'a b'\n=> 0\n+ ls | wc -l\n=> 0 | 0\n"
+            );
+        }
+
+        #[test]
+        fn branch_taken() {
+            let script = "if test -f x { echo yes } else { echo no }\n";
+
+            assert_eq!(
+                trace(script, 1),
+                "if\n  + test -f x\n  => 1\nelse\n  + echo no\n  => 0\n"
+            );
+        }
+
+        #[test]
+        fn loop_iterations() {
+            let script = "for x in a b { echo $x }\n";
+
+            assert_eq!(
+                trace(script, 0),
+                "for x\n  x = a\n    + echo a\n    => 0\n  x = b\n    + echo b\n    => 0\n"
+            );
+        }
+
+        #[test]
+        fn nested_blocks() {
+            let script = "{ if true { echo $(date) } }\n";
+
+            assert_eq!(
+                trace(script, 0),
+                "block\n  if\n    + true\n    => 0\n  then\n    + $(date)\n    => 0\n    \
+                 + echo ''\n    => 0\n"
+            );
+        }
+
+        #[test]
+        fn builtins() {
+            let script = "cd /\npwd > out.txt\ncd /nonexistent\n";
+
+            assert_eq!(
+                trace(script, 0),
+                "+ cd /\n=> 0\n+ pwd > out.txt\n=> 0\n+ cd /nonexistent\n=> 1\n"
+            );
+        }
+
+        #[test]
+        fn function_calls() {
+            let script = "fn greet(who) { echo hi $who }\ngreet you\n";
+
+            assert_eq!(
+                trace(script, 0),
+                "+ greet you\n  + echo hi you\n  => 0\n=> 0\n"
+            );
+        }
+    }
+
+    #[test]
+    fn command_not_found() {
+        let error = StdExecutor
+            .execute(&Invocation {
+                command: OsStr::new("cinnamon-no-such-command").into(),
+                args: vec![],
+                redirects: vec![],
+                dir: std::env::temp_dir(),
+                env: BTreeMap::new(),
+            })
+            .unwrap_err();
+
+        assert!(matches!(
+            &error,
+            Error::Spawn { command, .. } if command == "cinnamon-no-such-command"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "command not found: cinnamon-no-such-command"
+        );
+    }
+
+    #[test]
+    fn missing_redirect_source() {
+        let error = Streams::default()
+            .redirect(
+                &[Redirect::Read("cinnamon-no-such-file".into())],
+                Path::new("/"),
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            &error,
+            Error::Redirect { purpose: "reading", error, .. }
+                if error.kind() == io::ErrorKind::NotFound
+        ));
+        assert_eq!(
+            error.to_string(),
+            "cannot open cinnamon-no-such-file for reading: No such file or directory (os error 2)"
+        );
+    }
+}
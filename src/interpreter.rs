@@ -0,0 +1,266 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::Error;
+
+use crate::ast::{Block, PipeStatus};
+use crate::builtins::{Context, Status};
+use crate::env::Env;
+use crate::executor::{Executor, StdExecutor};
+use crate::parse::{Parse, ParseErrors};
+use crate::repl;
+
+/// A parsed program, ready to be run any number of times.
+#[derive(Debug)]
+pub struct Script {
+    name: String,
+    block: Block,
+}
+
+impl Script {
+    /// Parses `source`, naming it `name` in error messages.
+    ///
+    /// ```
+    /// use cinnamon::Script;
+    ///
+    /// let error = Script::parse("deploy.cm", "if true {").unwrap_err();
+    /// assert!(error.to_string().starts_with("deploy.cm:1:"));
+    /// ```
+    pub fn parse<S: Into<Vec<u8>>>(name: &str, source: S) -> Result<Script, Error> {
+        let ast = source.into().parse().map_err(|e| e.in_file(name))?;
+        Ok(Script {
+            name: name.to_owned(),
+            block: Block::new(ast),
+        })
+    }
+
+    /// Parses as much of `source` as possible, skipping each statement with a syntax error up to
+    /// the end of its line or the next `;` or `}`. Returns a script of the statements that parsed
+    /// along with the errors, which are empty if it all parsed.
+    ///
+    /// ```
+    /// use cinnamon::{Interpreter, Script};
+    ///
+    /// let (script, errors) = Script::parse_partial("deploy.cm", "true\nls >\nexit 3\n");
+    /// assert_eq!(errors.0.len(), 1);
+    /// assert!(errors.to_string().starts_with("deploy.cm:2:"));
+    /// assert_eq!(Interpreter::new().run(&script).unwrap(), 3);
+    /// ```
+    pub fn parse_partial<S: Into<Vec<u8>>>(name: &str, source: S) -> (Script, ParseErrors) {
+        let (ast, errors) = source.into().parse_partial();
+        let script = Script {
+            name: name.to_owned(),
+            block: Block::new(ast),
+        };
+        (script, ParseErrors(errors).in_file(name))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Runs scripts against one environment, so variables, functions and the working directory carry
+/// over from one script to the next.
+///
+/// ```
+/// use cinnamon::Interpreter;
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.eval("let greeting = hello\nlet code = $(printf 3)\n").unwrap();
+///
+/// assert_eq!(interpreter.variable("greeting"), Some("hello"));
+/// assert_eq!(interpreter.variable("code"), Some("3"));
+/// assert_eq!(interpreter.eval("false\n").unwrap(), 1);
+/// assert_eq!(interpreter.exit_status(), Some(1));
+/// ```
+pub struct Interpreter<E = StdExecutor> {
+    executor: E,
+    env: Env,
+    exit_status: Option<i32>,
+}
+
+impl Interpreter {
+    /// An interpreter that runs commands as processes, starting in this process's directory and
+    /// environment.
+    pub fn new() -> Self {
+        Interpreter::with_executor(StdExecutor)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl<E: Executor> Interpreter<E> {
+    /// An interpreter that hands every command to `executor` instead of spawning it.
+    pub fn with_executor(executor: E) -> Self {
+        Interpreter {
+            executor,
+            env: Env::new(),
+            exit_status: None,
+        }
+    }
+
+    /// Runs `script` to the end, returning the code a process running it should exit with.
+    pub fn run(&mut self, script: &Script) -> Result<i32, Error> {
+        let outer = self.env.set_file(Some(script.name.clone()));
+        let code = script
+            .block
+            .run_script_with(&mut self.executor, &mut self.env)
+            .map_err(|e| e.in_file(&script.name));
+        self.env.set_file(outer);
+        self.exit_status = code.as_ref().ok().cloned();
+        code
+    }
+
+    /// Parses and runs `source` in one go.
+    pub fn eval<S: Into<Vec<u8>>>(&mut self, source: S) -> Result<i32, Error> {
+        self.run(&Script::parse("<eval>", source)?)
+    }
+
+    /// Reads, parses and runs the script at `path`.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<i32, Error> {
+        let path = path.as_ref();
+        let mut contents = vec![];
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut contents))
+            .map_err(|source| Error::Open {
+                path: path.to_owned(),
+                source,
+            })?;
+        self.run(&Script::parse(&path.to_string_lossy(), contents)?)
+    }
+
+    /// Reads statements from the terminal and runs them until end of input or `exit`.
+    pub fn interact(&mut self) -> Result<i32, Error> {
+        let code = repl::run(&mut self.executor, &mut self.env);
+        self.exit_status = code.as_ref().ok().cloned();
+        code
+    }
+
+    /// The code the last script exited with, or `None` if none has finished yet.
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    /// The value of the variable `name` as the scripts left it, including exported variables.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.env.get(name).ok()
+    }
+
+    pub fn set_variable<S: ToString>(&mut self, name: &str, value: S) {
+        self.env.define(name, value);
+    }
+
+    /// Sets a variable that commands see in their environment.
+    pub fn export<S: Into<OsString>>(&mut self, name: &str, value: S) {
+        self.env.export(name, value);
+    }
+
+    /// Sets what scripts see as `$0` and as `$1`, `$2`, ... and `$@`.
+    pub fn set_arguments<S: Into<OsString>>(&mut self, script: S, arguments: Vec<OsString>) {
+        self.env.set_arguments(script, arguments);
+    }
+
+    /// Makes `builtin` run in this process whenever a script runs a command named `name`, unless
+    /// a function shadows it. It replaces any builtin already called that.
+    pub fn define_builtin<F>(&mut self, name: &str, builtin: F)
+    where
+        F: Fn(&[String], &mut Context) -> Result<Status, Error> + 'static,
+    {
+        self.env.define_builtin(name, builtin);
+    }
+
+    /// Makes a pipeline fail if any of its stages fail, not just the last.
+    pub fn set_pipefail(&mut self, pipefail: bool) {
+        let status = if pipefail {
+            PipeStatus::AnyFailure
+        } else {
+            PipeStatus::default()
+        };
+        self.env.set_pipe_status(status);
+    }
+
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    pub fn executor_mut(&mut self) -> &mut E {
+        &mut self.executor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::DryRunExecutor;
+
+    fn interpreter() -> Interpreter<DryRunExecutor<Vec<u8>>> {
+        Interpreter::with_executor(DryRunExecutor::with_output(Vec::new(), 1))
+    }
+
+    #[test]
+    fn state_carries_over_between_scripts() {
+        let mut interpreter = interpreter();
+        interpreter
+            .eval("let x = 1\nfn show() { echo $x }\n")
+            .unwrap();
+        interpreter.eval("x = 2\nshow\n").unwrap();
+
+        assert_eq!(interpreter.variable("x"), Some("2"));
+        assert_eq!(interpreter.executor().output(), b"show\necho 2\n");
+    }
+
+    #[test]
+    fn variables_set_by_the_host() {
+        let mut interpreter = interpreter();
+        interpreter.set_variable("name", "world");
+        interpreter.set_arguments("greet.cm", vec!["hi".into()]);
+        interpreter.eval("echo $1 $name $0\n").unwrap();
+
+        assert_eq!(interpreter.executor().output(), b"echo hi world greet.cm\n");
+    }
+
+    #[test]
+    fn undefined_variable() {
+        assert_eq!(interpreter().variable("cinnamon_no_such_variable"), None);
+    }
+
+    #[test]
+    fn exit_status_of_last_script() {
+        let mut interpreter = interpreter();
+        assert_eq!(interpreter.exit_status(), None);
+
+        assert_eq!(interpreter.eval("exit 3\n").unwrap(), 3);
+        assert_eq!(interpreter.exit_status(), Some(3));
+    }
+
+    #[test]
+    fn errors_name_the_script() {
+        let script = Script::parse("deploy.cm", "echo $missing\n").unwrap();
+        let error = interpreter().run(&script).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "deploy.cm:1:1: undefined variable: missing"
+        );
+    }
+
+    #[test]
+    fn missing_file_is_named() {
+        let error = interpreter()
+            .run_file("/nonexistent/deploy.cm")
+            .unwrap_err();
+
+        let path = Path::new("/nonexistent/deploy.cm");
+        assert!(matches!(&error, Error::Open { path: p, .. } if p == path));
+        assert!(error
+            .to_string()
+            .starts_with("cannot open /nonexistent/deploy.cm: "));
+    }
+}
@@ -0,0 +1,100 @@
+//! A shell language for gluing Unix tools together, without the edge cases `sh` keeps for
+//! compatibility.
+//!
+//! The `cinnamon` binary is a thin layer over [`Interpreter`], which programs can embed to run
+//! scripts themselves. Commands are started by an [`Executor`], so a host can decide how, or
+//! whether, each one runs:
+//!
+//! ```
+//! use cinnamon::{Error, Executor, Forked, Internal, Interpreter, Invocation, Streams, Success};
+//!
+//! /// Succeeds at everything, remembering what it was asked to run.
+//! #[derive(Default)]
+//! struct Recorder(Vec<String>);
+//!
+//! struct Status(i32);
+//!
+//! impl Success for Status {
+//!     fn success(&self) -> bool {
+//!         self.0 == 0
+//!     }
+//!
+//!     fn from_code(code: i32) -> Self {
+//!         Status(code)
+//!     }
+//!
+//!     fn exit_code(&self) -> i32 {
+//!         self.0
+//!     }
+//! }
+//!
+//! impl Executor for Recorder {
+//!     type ExitStatus = Status;
+//!
+//!     fn execute(&mut self, invocation: &Invocation) -> Result<Status, Error> {
+//!         self.0.push(invocation.to_string());
+//!         Ok(Status(0))
+//!     }
+//!
+//!     fn capture(&mut self, invocation: &Invocation) -> Result<(Status, String), Error> {
+//!         Ok((self.execute(invocation)?, "captured".to_owned()))
+//!     }
+//!
+//!     fn pipeline(&mut self, stages: &[Invocation]) -> Result<Vec<Status>, Error> {
+//!         stages.iter().map(|stage| self.execute(stage)).collect()
+//!     }
+//!
+//!     /// Nothing starts, so the shell never forks; the stages are only recorded.
+//!     fn fork_pipeline(&mut self, stages: &[Invocation]) -> Result<Forked<Status>, Error> {
+//!         self.pipeline(stages).map(Forked::Parent)
+//!     }
+//!
+//!     fn fork(
+//!         &mut self,
+//!         child: &mut dyn FnMut(&mut Self) -> Result<Status, Error>,
+//!     ) -> Result<Status, Error> {
+//!         child(self)
+//!     }
+//!
+//!     /// Builtins like `cd` still run, so later commands see their effects, but write nowhere.
+//!     fn internal(
+//!         &mut self,
+//!         invocation: &Invocation,
+//!         _inherited: Streams,
+//!         run: &mut Internal<'_, Self>,
+//!     ) -> Result<Status, Error> {
+//!         self.0.push(invocation.to_string());
+//!         run(self, None)
+//!     }
+//! }
+//!
+//! let mut interpreter = Interpreter::with_executor(Recorder::default());
+//! interpreter.eval("for x in a b { touch \"$x $(date)\" }\npwd | rm -rf /tmp/x\n").unwrap();
+//!
+//! assert_eq!(
+//!     interpreter.executor().0,
+//!     vec!["date", "touch 'a captured'", "date", "touch 'b captured'", "pwd", "rm -rf /tmp/x"],
+//! );
+//! ```
+
+extern crate libc;
+extern crate nom;
+
+mod ast;
+mod builtins;
+mod env;
+mod error;
+mod executor;
+mod interpreter;
+mod parse;
+mod repl;
+
+pub use crate::ast::{Fd, Redirect, Span};
+pub use crate::builtins::{Context, Status};
+pub use crate::error::Error;
+pub use crate::executor::{
+    DryRunExecutor, Executor, Forked, Internal, Invocation, StdExecutor, Streams, Success,
+    TraceExecutor,
+};
+pub use crate::interpreter::{Interpreter, Script};
+pub use crate::parse::{ParseError, ParseErrors};
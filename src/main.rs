@@ -1,38 +1,218 @@
-extern crate failure;
-#[macro_use]
-extern crate nom;
+#![allow(non_local_definitions)]
+
+extern crate cinnamon;
+extern crate libc;
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
 
-mod ast;
-mod parse;
-
-use crate::parse::*;
-use failure::*;
-use std::fs::*;
-use std::io::Read;
+use cinnamon::Error;
+use cinnamon::{DryRunExecutor, Executor, Interpreter, Script, StdExecutor, TraceExecutor};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use structopt::*;
 
 #[derive(StructOpt)]
 struct Cinnamon {
-    #[structopt(help = "Input file.")]
-    filename: String,
+    #[structopt(
+        help = "Input file, or - for standard input. Starts an interactive shell if omitted and \
+                standard input is a terminal.",
+        parse(from_os_str)
+    )]
+    filename: Option<OsString>,
+
+    #[structopt(
+        help = "Arguments for the script, available as $1, $2, ... and $@.",
+        parse(from_os_str)
+    )]
+    args: Vec<OsString>,
+
+    #[structopt(
+        short = "c",
+        help = "Program to run instead of a file. Any arguments that follow become $0, $1, ...",
+        parse(from_os_str)
+    )]
+    command: Option<OsString>,
+
+    #[structopt(
+        long = "pipefail",
+        help = "A pipeline fails if any of its stages fail, not just the last."
+    )]
+    pipefail: bool,
+
+    #[structopt(
+        long = "dry-run",
+        help = "Print the commands that would run instead of running them."
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long = "assume-status",
+        help = "The status conditions in `if`, `while`, `&&` and `||` get during a dry run.",
+        default_value = "0"
+    )]
+    assume_status: i32,
+
+    #[structopt(
+        long = "trace",
+        help = "Log each command to standard error as it runs, with its status and how long it \
+                took."
+    )]
+    trace: bool,
+
+    #[structopt(
+        long = "trace-file",
+        help = "Like --trace, but log to this file instead.",
+        parse(from_os_str)
+    )]
+    trace_file: Option<PathBuf>,
 }
 
-fn main() -> Result<(), Error> {
-    let args = Cinnamon::from_args();
-    let mut file = File::open(args.filename)?;
+fn main() -> ExitCode {
+    match run(parse_args(std::env::args_os().collect())) {
+        Ok(code) => ExitCode::from((code & 0xff) as u8),
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Everything after the script belongs to it, even arguments that look like our own flags, so
+/// `./deploy.cm --help` reaches the script.
+fn parse_args(mut argv: Vec<OsString>) -> Cinnamon {
+    let script = (1..argv.len()).find(|&i| {
+        Cinnamon::clap()
+            .get_matches_from_safe(&argv[..=i])
+            .map(|matches| matches.is_present("filename"))
+            .unwrap_or(false)
+    });
+    let script_args = match script {
+        Some(i) => argv.split_off(i + 1),
+        None => Vec::new(),
+    };
+
+    let mut args = Cinnamon::from_clap(Cinnamon::clap().get_matches_from(argv));
+    args.args = script_args;
+    args
+}
+
+fn run(args: Cinnamon) -> Result<i32, Error> {
+    if args.dry_run {
+        let executor = DryRunExecutor::new(args.assume_status);
+        run_traced(executor, args)
+    } else {
+        run_traced(StdExecutor, args)
+    }
+}
+
+fn run_traced<E: Executor>(executor: E, args: Cinnamon) -> Result<i32, Error> {
+    let out: Box<dyn Write> = match &args.trace_file {
+        Some(path) => Box::new(File::create(path).map_err(|source| Error::Open {
+            path: path.to_owned(),
+            source,
+        })?),
+        None if args.trace => Box::new(io::stderr()),
+        None => return run_with(Interpreter::with_executor(executor), args),
+    };
+    run_with(
+        Interpreter::with_executor(TraceExecutor::new(executor, out)),
+        args,
+    )
+}
+
+fn run_with<E: Executor>(mut interpreter: Interpreter<E>, args: Cinnamon) -> Result<i32, Error> {
+    interpreter.set_pipefail(args.pipefail);
+
+    match (args.command, args.filename) {
+        (Some(code), script) => {
+            let name = script.unwrap_or_else(|| "cinnamon".into());
+            interpreter.set_arguments(name, args.args);
+            interpreter.run(&Script::parse("-c", code.into_vec())?)
+        }
+        (None, Some(ref filename)) if filename == "-" => {
+            interpreter.set_arguments(filename, args.args);
+            run_stdin(&mut interpreter)
+        }
+        (None, Some(filename)) => {
+            interpreter.set_arguments(&filename, args.args);
+            interpreter.run_file(&filename)
+        }
+        (None, None) if !stdin_is_terminal() => run_stdin(&mut interpreter),
+        (None, None) => interpreter.interact(),
+    }
+}
+
+fn run_stdin<E: Executor>(interpreter: &mut Interpreter<E>) -> Result<i32, Error> {
     let mut contents = vec![];
-    file.read_to_end(&mut contents)?;
+    io::stdin().read_to_end(&mut contents)?;
+    interpreter.run(&Script::parse("<stdin>", contents)?)
+}
+
+fn stdin_is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) != 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Cinnamon {
+        parse_args(argv.iter().map(OsString::from).collect())
+    }
+
+    #[test]
+    fn script_arguments() {
+        let args = parse(&["cinnamon", "deploy.cm", "prod", "v1.2"]);
+
+        assert_eq!(args.filename, Some("deploy.cm".into()));
+        assert_eq!(args.args, vec!["prod", "v1.2"]);
+    }
 
-    let ast = contents
-        .parse()
-        .map_err(|_| err_msg("Could not parse file"))?;
+    #[test]
+    fn flags_after_script_belong_to_it() {
+        let args = parse(&["cinnamon", "deploy.cm", "--pipefail", "--help"]);
 
-    for statement in ast {
-        statement.execute()?;
+        assert!(!args.pipefail);
+        assert_eq!(args.args, vec!["--pipefail", "--help"]);
     }
 
-    Ok(())
+    #[test]
+    fn flags_before_script() {
+        let args = parse(&["cinnamon", "--pipefail", "deploy.cm", "prod"]);
+
+        assert!(args.pipefail);
+        assert_eq!(args.filename, Some("deploy.cm".into()));
+        assert_eq!(args.args, vec!["prod"]);
+    }
+
+    #[test]
+    fn inline_command_with_arguments() {
+        let args = parse(&["cinnamon", "-c", "echo $1;", "name", "prod"]);
+
+        assert_eq!(args.command, Some("echo $1;".into()));
+        assert_eq!(args.filename, Some("name".into()));
+        assert_eq!(args.args, vec!["prod"]);
+    }
+
+    #[test]
+    fn non_utf8_script_name() {
+        let name = OsString::from_vec(b"caf\xe9.cm".to_vec());
+        let args = parse_args(vec!["cinnamon".into(), name.clone(), "x".into()]);
+
+        assert_eq!(args.filename, Some(name));
+        assert_eq!(args.args, vec!["x"]);
+    }
+
+    #[test]
+    fn stdin_script() {
+        let args = parse(&["cinnamon", "-", "prod"]);
+
+        assert_eq!(args.filename, Some("-".into()));
+        assert_eq!(args.args, vec!["prod"]);
+    }
 }
@@ -1,50 +1,577 @@
+/// Runs a parser, noting what was expected if it fails so that a failed parse can say what went
+/// wrong at the furthest point it reached. With `at:`, the error points there instead, like at the
+/// quote that opened an unterminated string.
+macro_rules! expect (
+    ($i:expr, $message:expr, at: $at:expr, $submac:ident!( $($args:tt)* )) => ({
+        let input = $i;
+        let result = $submac!(input, $($args)*);
+        if result.is_incomplete() {
+            $crate::parse::expected(&input[input.len()..], $at, $message);
+        } else if result.is_err() {
+            $crate::parse::expected(input, $at, $message);
+        }
+        result
+    });
+    ($i:expr, $message:expr, $submac:ident!( $($args:tt)* )) => ({
+        let input = $i;
+        expect!(input, $message, at: input, $submac!($($args)*))
+    });
+    ($i:expr, $message:expr, $f:expr) => (
+        expect!($i, $message, call!($f))
+    );
+);
+
+mod assignment;
 mod command;
 mod comment;
 mod escaped;
+mod for_stmt;
+mod function;
 mod if_stmt;
+mod loop_control;
+mod policy;
+mod predicate;
+mod subshell;
+mod try_stmt;
+mod variable;
 mod while_stmt;
 
+use self::assignment::*;
 use self::command::*;
 use self::comment::*;
+use self::for_stmt::*;
+use self::function::*;
 use self::if_stmt::*;
+use self::loop_control::*;
+use self::policy::*;
+use self::predicate::*;
+use self::subshell::*;
+use self::try_stmt::*;
 use self::while_stmt::*;
 use crate::ast::*;
 use nom::*;
+use std::cell::{Cell, RefCell};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
 
 pub trait Parse: Sized {
-    fn parse(self) -> Result<Vec<AST>, ErrorKind>;
+    /// Parses a whole script, failing with every syntax error in it.
+    fn parse(self) -> Result<Vec<AST>, ParseErrors> {
+        match self.parse_partial() {
+            (ast, ref errors) if errors.is_empty() => Ok(ast),
+            (_, errors) => Err(ParseErrors(errors)),
+        }
+    }
+
+    /// Parses as much of a script as possible, skipping each statement with a syntax error up to
+    /// the end of its line or the next `;` or `}`. Returns the statements that parsed along with
+    /// the errors.
+    fn parse_partial(self) -> (Vec<AST>, Vec<ParseError>);
 }
 
 impl Parse for Vec<u8> {
-    fn parse(self) -> Result<Vec<AST>, ErrorKind> {
-        parse(&self[..])
-            .to_result()
-            .map_err(|e| e.into_error_kind())
+    fn parse_partial(self) -> (Vec<AST>, Vec<ParseError>) {
+        FURTHEST.with(|furthest| furthest.set(None));
+        SOURCE.with(|source| *source.borrow_mut() = Source::new(&self));
+        ERRORS.with(|errors| *errors.borrow_mut() = Some(Recovery::default()));
+        PARENS.with(|parens| parens.set(0));
+
+        let mut statements = Vec::new();
+        let mut input = &self[..];
+        while let IResult::Done(rest, parsed) = parse_tree(input) {
+            statements.extend(parsed);
+            if rest.is_empty() {
+                break;
+            }
+            // Only a `}` without a block to close stops a recovering parse early.
+            report(rest);
+            input = &rest[1..];
+        }
+
+        let recovery = ERRORS
+            .with(|errors| errors.borrow_mut().take())
+            .unwrap_or_default();
+        SOURCE.with(|source| *source.borrow_mut() = Source::default());
+        (statements, recovery.errors)
+    }
+}
+
+/// All the syntax errors in a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl ParseErrors {
+    /// Names the file the source came from, so each error can say where it is.
+    pub fn in_file(self, file: &str) -> ParseErrors {
+        ParseErrors(self.0.into_iter().map(|e| e.in_file(file)).collect())
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseErrors {}
+
+/// Why a script could not be parsed, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    file: Option<String>,
+    line: usize,
+    column: usize,
+    message: &'static str,
+    source_line: String,
+}
+
+impl ParseError {
+    fn new(source: &[u8], mut offset: usize, message: &'static str) -> ParseError {
+        // Past the end, point just after the last thing written rather than at a blank line.
+        if offset == source.len() {
+            while offset > 0 && source[offset - 1].is_ascii_whitespace() {
+                offset -= 1;
+            }
+        }
+        let (line, column) = locate(source, offset);
+        let start = line_start(source, offset);
+        let line_end = source[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(source.len(), |i| offset + i);
+        ParseError {
+            file: None,
+            line,
+            column,
+            message,
+            source_line: String::from_utf8_lossy(&source[start..line_end])
+                .trim_end_matches('\r')
+                .to_owned(),
+        }
+    }
+
+    /// Names the file the source came from, so the error can say where it is.
+    pub fn in_file<S: Into<String>>(self, file: S) -> ParseError {
+        ParseError {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        writeln!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        writeln!(f, "{}", self.source_line)?;
+        // Keep tabs so the caret lines up however wide the terminal draws them.
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}^", indent)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Where the line containing `offset` starts.
+fn line_start(source: &[u8], offset: usize) -> usize {
+    source[..offset]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1)
+}
+
+/// The line and column of `offset`, both counting from 1. Columns count characters, not bytes.
+fn locate(source: &[u8], offset: usize) -> (usize, usize) {
+    let start = line_start(source, offset);
+    let line = source[..start].iter().filter(|&&b| b == b'\n').count() + 1;
+    let column = String::from_utf8_lossy(&source[start..offset])
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// The input being parsed, so parsers can work out the spans of what they parse.
+#[derive(Default)]
+struct Source {
+    address: usize,
+    bytes: Vec<u8>,
+    line_starts: Vec<usize>,
+}
+
+impl Source {
+    fn new(bytes: &[u8]) -> Source {
+        let newlines = bytes.iter().enumerate().filter(|&(_, &b)| b == b'\n');
+        Source {
+            address: bytes.as_ptr() as usize,
+            bytes: bytes.to_owned(),
+            line_starts: std::iter::once(0)
+                .chain(newlines.map(|(i, _)| i + 1))
+                .collect(),
+        }
+    }
+
+    /// Like `locate`, without counting lines from the start every time.
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let start = self.line_starts[line];
+        let column = String::from_utf8_lossy(&self.bytes[start..offset])
+            .chars()
+            .count();
+        (line + 1, column + 1)
+    }
+
+    fn offset(&self, input: &[u8]) -> Option<usize> {
+        let offset = (input.as_ptr() as usize).checked_sub(self.address)?;
+        if offset <= self.bytes.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+}
+
+thread_local! {
+    static SOURCE: RefCell<Source> = RefCell::new(Source::default());
+}
+
+/// The errors found so far while parsing with recovery.
+#[derive(Default)]
+struct Recovery {
+    errors: Vec<ParseError>,
+    /// Whether an error ran into the end of the input, after which the rest is just fallout.
+    ended: bool,
+}
+
+thread_local! {
+    /// `None` when not recovering, in which case a statement that fails to parse ends the
+    /// statements as it always has.
+    static ERRORS: RefCell<Option<Recovery>> = const { RefCell::new(None) };
+}
+
+fn recovering() -> bool {
+    ERRORS.with(|errors| errors.borrow().is_some())
+}
+
+/// Records why the statement at `input` failed to parse.
+fn report(input: &[u8]) {
+    let expected = FURTHEST.with(Cell::take);
+    SOURCE.with(|source| {
+        let source = source.borrow();
+        let (offset, message, ends) = match expected {
+            // Anything noted before this statement started was about an earlier one.
+            Some(expected) if expected.progress >= input.as_ptr() as usize => (
+                expected.at.saturating_sub(source.address),
+                expected.message,
+                expected.progress >= source.address + source.bytes.len(),
+            ),
+            _ => (
+                source.offset(input).unwrap_or(0),
+                "expected a statement",
+                input.is_empty(),
+            ),
+        };
+        let error = ParseError::new(&source.bytes, offset.min(source.bytes.len()), message);
+        ERRORS.with(|errors| {
+            if let Some(recovery) = errors.borrow_mut().as_mut() {
+                if !recovery.ended && recovery.errors.last() != Some(&error) {
+                    recovery.errors.push(error);
+                }
+                recovery.ended |= ends;
+            }
+        });
+    });
+}
+
+/// Skips the statement at `input` that failed to parse: up to just after the next `;` or newline,
+/// or the `}` closing a block the statement opened, or up to a `}` closing the enclosing block.
+fn synchronize(input: &[u8]) -> &[u8] {
+    let (mut braces, mut parens, mut quoted) = (0, 0, false);
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'\\' => i += 1,
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'#' => {
+                i += input[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .unwrap_or(input.len() - i);
+                continue;
+            }
+            b'(' => parens += 1,
+            b')' => parens -= 1,
+            b'{' => braces += 1,
+            b'}' if braces == 0 => return &input[i..],
+            b'}' => {
+                braces -= 1;
+                if braces == 0 {
+                    return &input[i + 1..];
+                }
+            }
+            b';' | b'\n' if braces == 0 && parens <= 0 => return &input[i + 1..],
+            _ => {}
+        }
+        i += 1;
+    }
+    &input[input.len()..]
+}
+
+/// Matches nothing, returning the rest of the input so a parser can note where it is.
+pub(crate) fn here(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    IResult::Done(input, input)
+}
+
+/// The span from `start` to `end`, both positions from `here`. Parsers run on their own, outside
+/// of `Parse::parse`, don't know where they are and give the default span.
+pub(crate) fn span(start: &[u8], end: &[u8]) -> Span {
+    SOURCE.with(|source| {
+        let source = source.borrow();
+        match (source.offset(start), source.offset(end)) {
+            (Some(start), Some(end)) => {
+                let (line, column) = source.locate(start);
+                Span {
+                    start,
+                    end,
+                    line,
+                    column,
+                }
+            }
+            _ => Span::default(),
+        }
+    })
+}
+
+/// The furthest a parse got before something it expected wasn't there.
+#[derive(Clone, Copy)]
+struct Expected {
+    progress: usize,
+    at: usize,
+    message: &'static str,
+}
+
+thread_local! {
+    static FURTHEST: Cell<Option<Expected>> = const { Cell::new(None) };
+}
+
+/// Notes that `message` describes a failure `progress` into the input, pointing at `at`, unless a
+/// failure has already been noted at least as far along.
+pub(crate) fn expected(progress: &[u8], at: &[u8], message: &'static str) {
+    fn address(input: &[u8]) -> usize {
+        let whitespace = input.iter().take_while(|b| b" \t\r".contains(b)).count();
+        input.as_ptr() as usize + whitespace
+    }
+    let expected = Expected {
+        progress: address(progress),
+        at: address(at),
+        message,
+    };
+    FURTHEST.with(|furthest| match furthest.get() {
+        Some(previous) if previous.progress >= expected.progress => {}
+        _ => furthest.set(Some(expected)),
+    });
+}
+
+thread_local! {
+    /// How many parentheses enclose what's being parsed. Inside them a newline can't end the
+    /// statement, so it's just whitespace.
+    static PARENS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs `parser` on what's inside a pair of parentheses.
+pub(crate) fn in_parens<'a, O, F>(input: &'a [u8], parser: F) -> IResult<&'a [u8], O>
+where
+    F: Fn(&'a [u8]) -> IResult<&'a [u8], O>,
+{
+    PARENS.with(|parens| parens.set(parens.get() + 1));
+    let result = parser(input);
+    PARENS.with(|parens| parens.set(parens.get() - 1));
+    result
+}
+
+/// Whitespace within a statement: spaces, tabs, and newlines that are escaped with `\` or inside
+/// parentheses. Incomplete if the input ends right after an escaped newline, since the statement
+/// goes on to the next line.
+pub(crate) fn blank(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let newlines = PARENS.with(Cell::get) > 0;
+    let mut escape = None;
+    let mut i = 0;
+    loop {
+        let len = match &input[i..] {
+            [b' ', ..] | [b'\t', ..] | [b'\r', ..] => 1,
+            [b'\n', ..] if newlines => 1,
+            [b'\\', b'\n', ..] => 2,
+            [b'\\', b'\r', b'\n', ..] => 3,
+            _ => break,
+        };
+        if input[i] == b'\\' {
+            escape = Some(i);
+        }
+        i += len;
+    }
+    match escape {
+        _ if i == 0 => IResult::Error(error_position!(ErrorKind::MultiSpace, input)),
+        Some(escape) if i == input.len() => {
+            expected(
+                &input[i..],
+                &input[escape..],
+                "expected the statement to go on after `\\`",
+            );
+            IResult::Incomplete(Needed::Unknown)
+        }
+        _ => IResult::Done(&input[i..], &input[..i]),
+    }
+}
+
+/// Whitespace after an operator like `|` or `&&`, which can't end a statement, so the rest of it
+/// may be on the next line. Incomplete if nothing follows yet.
+pub(crate) fn continuation(input: &[u8]) -> IResult<&[u8], ()> {
+    let whitespace = input.iter().take_while(|b| b" \t\r\n".contains(b)).count();
+    if whitespace == input.len() {
+        return IResult::Incomplete(Needed::Unknown);
+    }
+    IResult::Done(&input[whitespace..], ())
+}
+
+/// The end of a statement: a `;`, the end of the line, or the `}` of the block or the end of the
+/// input, which are left for what comes next.
+pub(crate) fn end_of_statement(input: &[u8]) -> IResult<&[u8], ()> {
+    let rest = match blank(input) {
+        IResult::Done(rest, _) => rest,
+        IResult::Incomplete(needed) => return IResult::Incomplete(needed),
+        IResult::Error(_) => input,
+    };
+    match rest.first() {
+        Some(b';') | Some(b'\n') => IResult::Done(&rest[1..], ()),
+        None | Some(b'}') => IResult::Done(rest, ()),
+        Some(_) => IResult::Error(error_position!(ErrorKind::Custom(0), rest)),
+    }
+}
+
+/// Matches the keyword `word`, but not as the start of a longer name like `iffy`.
+pub(crate) fn keyword<'a>(input: &'a [u8], word: &'static str) -> IResult<&'a [u8], &'a [u8]> {
+    let (rest, matched) = try_parse!(input, complete!(tag!(word)));
+    match rest.first() {
+        Some(&c) if variable::is_identifier_char(c) => {
+            IResult::Error(error_position!(ErrorKind::Tag, input))
+        }
+        _ => IResult::Done(rest, matched),
     }
 }
 
-fn into_string<'a>(bytes: &'a [u8]) -> String {
-    String::from_utf8(bytes.to_owned()).unwrap()
+/// Text from the script, where any bytes that aren't UTF-8 are replaced. Only used where the
+/// grammar allows nothing but ASCII, or for comments.
+fn into_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Bytes from the script taken as they are, since arguments and file names need not be UTF-8.
+fn into_os_string(bytes: &[u8]) -> OsString {
+    OsStr::from_bytes(bytes).to_owned()
 }
 
 named!(
     pub ast<AST>,
-    alt_complete!(
-        block => { |b| AST::Block(Block(b)) } |
-        if_stmt => { |cond| AST::If(cond) } |
-        while_stmt => { |stmt| AST::While(stmt) } |
-        comment => { |s| AST::Comment(s) } |
-        command_line => { |c| AST::Command(c) }
+    alt!(
+        block => { AST::Block } |
+        subshell => { AST::Subshell } |
+        if_stmt => { AST::If } |
+        while_stmt => { AST::While } |
+        for_stmt => { AST::For } |
+        try_stmt => { AST::Try } |
+        function => { AST::Function } |
+        return_stmt |
+        break_stmt |
+        continue_stmt |
+        let_stmt => { AST::Let } |
+        assign_stmt => { AST::Assign } |
+        comment => { AST::Comment } |
+        pragma => { AST::Pragma } |
+        pipeline_line => { AST::Pipeline } |
+        command_line => { AST::Command } |
+        predicate_line => { AST::Predicate }
 ));
 
-named!(
-    pub parse_tree<Vec<AST>>,
-    ws!(many0!(ast))
-);
+/// Whether `input` stops partway through a statement, like a `{` or `$(` that hasn't been closed
+/// yet or a line ending in `|`, so more input could still make it parse.
+pub fn is_unfinished(input: &[u8]) -> bool {
+    FURTHEST.with(|furthest| furthest.set(None));
+    match parse(input) {
+        IResult::Incomplete(_) => true,
+        // Some parsers give up at the end of the input rather than asking for more.
+        IResult::Error(_) => FURTHEST
+            .with(Cell::get)
+            .is_some_and(|expected| expected.progress >= input.as_ptr() as usize + input.len()),
+        IResult::Done(..) => false,
+    }
+}
+
+/// The statements up to the end of the input or a `}` closing the block they're in.
+pub fn parse_tree(input: &[u8]) -> IResult<&[u8], Vec<AST>> {
+    let mut statements = Vec::new();
+    let mut input = input;
+    loop {
+        let start = input.iter().take_while(|b| b" \t\r\n".contains(b)).count();
+        input = &input[start..];
+        if input.is_empty() {
+            return IResult::Done(input, statements);
+        }
+        match ast(input) {
+            IResult::Done(rest, statement) => {
+                statements.push(statement);
+                let consumed = &input[..input.len() - rest.len()];
+                input = match consumed.last() {
+                    Some(b'}') | Some(b')') => semicolon_after_block(rest),
+                    _ => rest,
+                };
+            }
+            IResult::Error(_) if input[0] == b'}' || !recovering() => {
+                return IResult::Done(input, statements);
+            }
+            IResult::Incomplete(needed) if !recovering() => return IResult::Incomplete(needed),
+            _ => {
+                report(input);
+                input = synchronize(input);
+            }
+        }
+    }
+}
+
+/// Skips a `;` after a statement that ends in a block, like `if` or `while`, which is optional
+/// since the block already ends it.
+fn semicolon_after_block(input: &[u8]) -> &[u8] {
+    let blanks = input.iter().take_while(|b| b" \t".contains(b)).count();
+    match input.get(blanks) {
+        Some(b';') => &input[blanks + 1..],
+        _ => input,
+    }
+}
 
 named!(
     parse<Vec<AST>>,
-    do_parse!(parse_tree: ws!(parse_tree) >> eof!() >> (parse_tree))
+    do_parse!(
+        parse_tree: ws!(parse_tree) >>
+        expect!("expected a statement", eof!()) >>
+        (parse_tree)
+    )
 );
 
 #[cfg(test)]
@@ -88,11 +615,606 @@ mod tests {
         );
     }
 
+    #[test]
+    fn let_then_command() {
+        let file: Vec<u8> = "let name = world;\necho \"hello $name\";".into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![
+                AST::Let(Assignment::new("name", "world".into())),
+                AST::Command(Command::with_words(
+                    "echo",
+                    vec![Word::new(vec![
+                        WordPart::Literal("hello ".into()),
+                        WordPart::Variable("name".into()),
+                    ])]
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn semicolon_after_block_statement() {
+        let file: Vec<u8> =
+            "if true { echo a }; echo b\nwhile true { break } ; echo after\n".into();
+
+        let ast = file.parse().unwrap();
+
+        assert_eq!(ast.len(), 4);
+        assert_eq!(ast[1], AST::Command(Command::new("echo", vec!["b"])));
+        assert_eq!(ast[3], AST::Command(Command::new("echo", vec!["after"])));
+    }
+
+    #[test]
+    fn only_one_semicolon_after_block_statement() {
+        let file: Vec<u8> = "{ ls };;\n".into();
+
+        assert!(file.parse().is_err());
+    }
+
+    #[test]
+    fn command_starting_with_let() {
+        let file: Vec<u8> = "letter;".into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![AST::Command(Command::new("letter", vec![]))]
+        );
+    }
+
+    #[test]
+    fn boolean_statement() {
+        let file: Vec<u8> = "test -d build || mkdir build;\necho done;".into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![
+                AST::Predicate(Predicate::or(
+                    Command::new("test", vec!["-d", "build"]).into(),
+                    Command::new("mkdir", vec!["build"]).into(),
+                )),
+                AST::Command(Command::new("echo", vec!["done"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn command_named_like_subshell_keyword() {
+        let file: Vec<u8> = "fork;".into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![AST::Command(Command::new("fork", vec![]))]
+        );
+    }
+
+    #[test]
+    fn pragma_then_block_with_policy() {
+        let file: Vec<u8> = "@continue
+@warn {
+  false
+}
+"
+        .into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![
+                AST::Pragma(FailurePolicy::Continue),
+                AST::Block(
+                    Block::new(vec![AST::Command(Command::new("false", vec![]))])
+                        .with_policy(FailurePolicy::Warn)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_body_with_policy() {
+        let file: Vec<u8> = "while true @continue { false }".into();
+
+        assert_eq!(
+            file.parse().unwrap(),
+            vec![AST::While(While::new(
+                Command::new("true", vec![]),
+                AST::Block(
+                    Block::new(vec![AST::Command(Command::new("false", vec![]))])
+                        .with_policy(FailurePolicy::Continue)
+                )
+            ))]
+        );
+    }
+
+    #[cfg(test)]
+    mod unfinished {
+        use super::*;
+
+        fn unfinished(s: &str) -> bool {
+            is_unfinished(s.as_bytes())
+        }
+
+        #[test]
+        fn complete_statements() {
+            assert!(!unfinished("echo foo;\nif true { echo bar; }\n"));
+        }
+
+        #[test]
+        fn empty() {
+            assert!(!unfinished(""));
+        }
+
+        #[test]
+        fn open_block() {
+            assert!(unfinished("if true {\n  echo foo;\n"));
+        }
+
+        #[test]
+        fn open_nested_block() {
+            assert!(unfinished(
+                "for x in a b {\n  if true { echo $x; }\n  while true {"
+            ));
+        }
+
+        #[test]
+        fn open_function() {
+            assert!(unfinished("fn greet(name) {"));
+        }
+
+        #[test]
+        fn after_complete_statement() {
+            assert!(unfinished("echo foo;\nsubshell {"));
+        }
+
+        #[test]
+        fn no_semicolon() {
+            assert!(!unfinished("echo foo\n"));
+        }
+
+        #[test]
+        fn escaped_newline() {
+            assert!(unfinished("echo foo \\\n"));
+        }
+
+        #[test]
+        fn trailing_operator() {
+            assert!(unfinished("ls |\n"));
+            assert!(unfinished("true &&\n"));
+            assert!(unfinished("false ||\n"));
+        }
+
+        #[test]
+        fn open_paren() {
+            assert!(unfinished("echo $(ls\n"));
+            assert!(unfinished("if (true\n"));
+        }
+
+        #[test]
+        fn open_else() {
+            assert!(unfinished("if true {} else {"));
+        }
+
+        #[test]
+        fn extra_close_is_error() {
+            assert!(!unfinished("echo foo; }"));
+        }
+
+        #[test]
+        fn brace_in_quotes_is_not_a_block() {
+            assert!(!unfinished("echo \"{\";"));
+        }
+    }
+
     #[test]
     fn trailing_characters() {
-        let file: Vec<u8> = vec![1];
+        let file: Vec<u8> = vec![b')'];
         let parse = file.parse();
 
         assert!(parse.is_err(), "Expected {:?} to be an error", parse);
     }
+
+    #[cfg(test)]
+    mod errors {
+        use super::*;
+
+        fn error(s: &str) -> ParseError {
+            s.as_bytes().to_vec().parse().unwrap_err().0.remove(0)
+        }
+
+        #[test]
+        fn unexpected_after_command() {
+            let error = error("echo foo\necho bar)\n");
+
+            assert_eq!((error.line, error.column), (2, 9));
+            assert_eq!(error.message, "expected `;` or newline after command");
+        }
+
+        #[test]
+        fn unexpected_after_pipeline() {
+            assert_eq!(
+                error("ls | wc )").message,
+                "expected `;` or newline after pipeline"
+            );
+        }
+
+        #[test]
+        fn operator_on_next_line() {
+            let error = error("true\n&& echo foo\n");
+
+            assert_eq!((error.line, error.column), (2, 1));
+        }
+
+        #[test]
+        fn escaped_newline_at_end() {
+            let error = error("echo foo \\\n");
+
+            assert_eq!((error.line, error.column), (1, 10));
+            assert_eq!(error.message, "expected the statement to go on after `\\`");
+        }
+
+        #[test]
+        fn unterminated_string_points_at_quote() {
+            let error = error("echo \"foo;\n");
+
+            assert_eq!((error.line, error.column), (1, 6));
+            assert_eq!(error.message, "unterminated string");
+        }
+
+        #[test]
+        fn unclosed_block() {
+            let error = error("if true {\n  echo foo;\n\n");
+
+            assert_eq!((error.line, error.column), (2, 12));
+            assert_eq!(error.message, "expected `}`");
+        }
+
+        #[test]
+        fn unclosed_block_after_pipeline() {
+            let error = error("if ls | grep -q foo {\n  echo foo;\n");
+
+            assert_eq!((error.line, error.column), (2, 12));
+            assert_eq!(error.message, "expected `}`");
+        }
+
+        #[test]
+        fn unexpected_close() {
+            let error = error("echo foo;\n}\n");
+
+            assert_eq!((error.line, error.column), (2, 1));
+            assert_eq!(error.message, "expected a statement");
+        }
+
+        #[test]
+        fn missing_command_after_operator() {
+            assert_eq!(error("true && ;").message, "expected a command after `&&`");
+            assert_eq!(error("ls | ;").message, "expected a command after `|`");
+        }
+
+        #[test]
+        fn missing_condition() {
+            assert_eq!(
+                error("while { }").message,
+                "expected a condition after `while`"
+            );
+        }
+
+        #[test]
+        fn keyword_prefix_is_a_command() {
+            assert_eq!(
+                error("iffy )").message,
+                "expected `;` or newline after command"
+            );
+        }
+
+        #[test]
+        fn columns_count_characters() {
+            assert_eq!(error("echo \"héllo\" \"").column, 14);
+        }
+
+        #[test]
+        fn display() {
+            let error = error("echo foo;\n\techo \"bar;\n").in_file("script.cm");
+
+            assert_eq!(
+                error.to_string(),
+                "script.cm:2:7: unterminated string\n\techo \"bar;\n\t     ^"
+            );
+        }
+
+        #[test]
+        fn display_without_file() {
+            assert_eq!(
+                error("echo foo)").to_string(),
+                "1:9: expected `;` or newline after command\necho foo)\n        ^"
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod spans {
+        use super::*;
+
+        fn parse(s: &str) -> Vec<AST> {
+            s.as_bytes().to_vec().parse().unwrap()
+        }
+
+        #[test]
+        fn command() {
+            let span = parse("  echo foo;\n")[0].span();
+
+            assert_eq!((span.start, span.end), (2, 10));
+            assert_eq!((span.line, span.column), (1, 3));
+        }
+
+        #[test]
+        fn statements_in_block() {
+            let ast = parse("echo foo;\n{\n  ls -l;\n}\n");
+
+            let block = match &ast[1] {
+                AST::Block(block) => block,
+                other => panic!("Expected block, got {:?}", other),
+            };
+            let (outer, inner) = (ast[1].span(), block.statements[0].span());
+
+            assert_eq!(
+                (outer.line, outer.column, outer.start, outer.end),
+                (2, 1, 10, 22)
+            );
+            assert_eq!((inner.line, inner.column), (3, 3));
+        }
+
+        #[test]
+        fn if_statement_covers_else() {
+            let span = parse("if true { a; } else { b; }")[0].span();
+
+            assert_eq!((span.start, span.end), (0, 26));
+        }
+
+        #[test]
+        fn predicate_covers_all_commands() {
+            let span = parse("a && b || c;")[0].span();
+
+            assert_eq!((span.start, span.end), (0, 11));
+        }
+
+        #[test]
+        fn columns_count_characters() {
+            let span = parse("echo \"é\"; ls;")[1].span();
+
+            assert_eq!((span.start, span.column), (11, 11));
+        }
+    }
+
+    #[cfg(test)]
+    mod recovery {
+        use super::*;
+
+        fn parse_partial(s: &str) -> (Vec<AST>, Vec<(usize, &'static str)>) {
+            let (ast, errors) = s.as_bytes().to_vec().parse_partial();
+            (ast, errors.iter().map(|e| (e.line, e.message)).collect())
+        }
+
+        #[test]
+        fn reports_every_error() {
+            let (_, errors) = parse_partial("ls > ;\necho ok;\ntrue && ;\n");
+
+            assert_eq!(
+                errors,
+                vec![
+                    (1, "expected a file name after `>`"),
+                    (3, "expected a command after `&&`"),
+                ]
+            );
+        }
+
+        #[test]
+        fn recovers_at_end_of_line() {
+            let (ast, errors) = parse_partial("ls >\necho a\nls | > out\necho b\n");
+
+            assert_eq!(
+                errors,
+                vec![
+                    (1, "expected a file name after `>`"),
+                    (3, "expected a command after `|`")
+                ]
+            );
+            assert_eq!(
+                ast,
+                vec![
+                    AST::Command(Command::new("echo", vec!["a"])),
+                    AST::Command(Command::new("echo", vec!["b"])),
+                ]
+            );
+        }
+
+        #[test]
+        fn keeps_statements_that_parse() {
+            let (ast, _) = parse_partial("echo a;\nls > ;\necho b;\n");
+
+            assert_eq!(
+                ast,
+                vec![
+                    AST::Command(Command::new("echo", vec!["a"])),
+                    AST::Command(Command::new("echo", vec!["b"])),
+                ]
+            );
+        }
+
+        #[test]
+        fn recovers_inside_block() {
+            let (ast, errors) = parse_partial("if true {\n  ls > ;\n  echo a;\n}\necho b;\n");
+
+            assert_eq!(errors, vec![(2, "expected a file name after `>`")]);
+            assert_eq!(
+                ast,
+                vec![
+                    AST::If(Conditional::new(
+                        Command::new("true", vec![]),
+                        AST::Block(Block::new(vec![AST::Command(Command::new(
+                            "echo",
+                            vec!["a"]
+                        ))])),
+                        None
+                    )),
+                    AST::Command(Command::new("echo", vec!["b"])),
+                ]
+            );
+        }
+
+        #[test]
+        fn skips_block_opened_by_bad_statement() {
+            let (ast, errors) = parse_partial("fn f( { echo a; }\necho b;\n");
+
+            assert_eq!(errors.len(), 1);
+            assert_eq!(ast, vec![AST::Command(Command::new("echo", vec!["b"]))]);
+        }
+
+        #[test]
+        fn semicolon_in_quotes_does_not_end_statement() {
+            let (ast, errors) = parse_partial("echo \"a;\" > ;\necho c;");
+
+            assert_eq!(errors.len(), 1);
+            assert_eq!(ast, vec![AST::Command(Command::new("echo", vec!["c"]))]);
+        }
+
+        #[test]
+        fn unmatched_close() {
+            let (ast, errors) = parse_partial("echo a;\n}\necho b;\n");
+
+            assert_eq!(errors, vec![(2, "expected a statement")]);
+            assert_eq!(ast.len(), 2);
+        }
+
+        #[test]
+        fn nothing_after_unterminated_string() {
+            let (_, errors) = parse_partial("if true {\n  echo \"a;\n}\nls > ;\n");
+
+            assert_eq!(errors, vec![(2, "unterminated string")]);
+        }
+
+        #[test]
+        fn display_lists_all() {
+            let errors = "ls > ;\nls < ;".as_bytes().to_vec().parse().unwrap_err();
+
+            assert_eq!(
+                errors.in_file("a.cm").to_string(),
+                "a.cm:1:6: expected a file name after `>`\nls > ;\n     ^\n\
+                 a.cm:2:6: expected a file name after `<`\nls < ;\n     ^"
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod bytes {
+        use super::*;
+
+        #[test]
+        fn non_utf8_argument() {
+            let file = b"touch caf\xe9.txt;".to_vec();
+
+            assert_eq!(
+                file.parse().unwrap(),
+                vec![AST::Command(Command::with_words(
+                    "touch",
+                    vec![Word::new(vec![WordPart::Literal(into_os_string(
+                        b"caf\xe9.txt"
+                    ))])]
+                ))]
+            );
+        }
+
+        #[test]
+        fn non_utf8_command_name() {
+            let errors = b"\xff\xfe arg;".to_vec().parse().unwrap_err();
+
+            assert_eq!(errors.0[0].message, "command name is not valid UTF-8");
+            assert_eq!((errors.0[0].line, errors.0[0].column), (1, 1));
+        }
+
+        #[test]
+        fn non_utf8_comment() {
+            assert!(b"# caf\xe9".to_vec().parse().is_ok());
+        }
+
+        /// A small xorshift generator, so the inputs are the same on every run.
+        struct Bytes(u64);
+
+        impl Bytes {
+            fn next(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+
+            /// Mostly pieces of the grammar, so inputs get past the first few characters, with
+            /// arbitrary bytes mixed in.
+            fn input(&mut self) -> Vec<u8> {
+                const PIECES: &[&[u8]] = &[
+                    b"if ",
+                    b"else ",
+                    b"while ",
+                    b"for ",
+                    b" in ",
+                    b"fn ",
+                    b"return",
+                    b"let ",
+                    b"subshell ",
+                    b"fork ",
+                    b"{",
+                    b"}",
+                    b"(",
+                    b")",
+                    b"$(",
+                    b"$",
+                    b"${",
+                    b"\"",
+                    b"\\",
+                    b";",
+                    b"|",
+                    b"&&",
+                    b"||",
+                    b"!",
+                    b">",
+                    b">>",
+                    b"2>&1",
+                    b"<",
+                    b"=",
+                    b"..",
+                    b"#",
+                    b"\n",
+                    b" ",
+                    b"\t",
+                    b"echo",
+                    b"x",
+                    b"~/",
+                    b"./a",
+                    b"1",
+                ];
+                let len = self.next() % 24;
+                let mut input = Vec::new();
+                for _ in 0..len {
+                    let n = self.next();
+                    if n.is_multiple_of(4) {
+                        input.push((n >> 8) as u8);
+                    } else {
+                        input.extend_from_slice(PIECES[(n >> 8) as usize % PIECES.len()]);
+                    }
+                }
+                input
+            }
+        }
+
+        #[test]
+        fn arbitrary_input_never_panics() {
+            let mut bytes = Bytes(0x2545_f491_4f6c_dd1d);
+            for _ in 0..10_000 {
+                let input = bytes.input();
+                is_unfinished(&input);
+                for error in input.parse_partial().1 {
+                    error.to_string();
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,116 @@
+use super::command::arg;
+use super::variable::identifier;
+use super::{blank, end_of_statement, here, keyword, span};
+use crate::ast::*;
+use nom::*;
+
+named!(
+    value<Word>,
+    delimited!(
+        complete!(delimited!(opt!(blank), char!('='), opt!(blank))),
+        expect!("expected a value after `=`", arg),
+        expect!("expected `;` or newline after value", end_of_statement)
+    )
+);
+
+named!(pub let_stmt<Assignment>, do_parse!(
+    start: here >>
+    call!(keyword, "let") >>
+    complete!(multispace) >>
+    name: expect!("expected a variable name after `let`", identifier) >>
+    value: expect!("expected `=` after variable name", value) >>
+    end: here >>
+    (Assignment::new(name, value).at(span(start, end)))
+));
+
+named!(pub assign_stmt<Assignment>, do_parse!(
+    start: here >>
+    name: identifier >>
+    value: value >>
+    end: here >>
+    (Assignment::new(name, value).at(span(start, end)))
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn let_bare_word() {
+        assert_eq!(
+            let_stmt(&b"let foo = bar;"[..]),
+            IResult::Done(&b""[..], Assignment::new("foo", "bar".into()))
+        );
+    }
+
+    #[test]
+    fn let_quoted() {
+        assert_eq!(
+            let_stmt(&b"let foo = \"bar baz\";"[..]),
+            IResult::Done(&b""[..], Assignment::new("foo", "bar baz".into()))
+        );
+    }
+
+    #[test]
+    fn let_without_spaces() {
+        assert_eq!(
+            let_stmt(&b"let foo=bar;"[..]),
+            IResult::Done(&b""[..], Assignment::new("foo", "bar".into()))
+        );
+    }
+
+    #[test]
+    fn let_requires_space_after_keyword() {
+        assert!(let_stmt(&b"letfoo = bar;"[..]).is_err());
+    }
+
+    #[test]
+    fn let_ends_at_newline() {
+        assert_eq!(
+            let_stmt(&b"let foo = bar\necho"[..]),
+            IResult::Done(&b"echo"[..], Assignment::new("foo", "bar".into()))
+        );
+    }
+
+    #[test]
+    fn let_ends_at_end_of_input() {
+        assert_eq!(
+            let_stmt(&b"let foo = bar"[..]),
+            IResult::Done(&b""[..], Assignment::new("foo", "bar".into()))
+        );
+    }
+
+    #[test]
+    fn let_takes_one_value() {
+        assert!(!let_stmt(&b"let foo = bar baz;"[..]).is_done());
+    }
+
+    #[test]
+    fn assign_does_not_continue_onto_next_line() {
+        assert!(assign_stmt(&b"foo\n= bar;"[..]).is_err());
+    }
+
+    #[test]
+    fn let_from_variable() {
+        assert_eq!(
+            let_stmt(&b"let foo = $bar;"[..]),
+            IResult::Done(
+                &b""[..],
+                Assignment::new("foo", Word::new(vec![WordPart::Variable("bar".into())]))
+            )
+        );
+    }
+
+    #[test]
+    fn reassignment() {
+        assert_eq!(
+            assign_stmt(&b"foo = bar;"[..]),
+            IResult::Done(&b""[..], Assignment::new("foo", "bar".into()))
+        );
+    }
+
+    #[test]
+    fn command_is_not_assignment() {
+        assert!(assign_stmt(&b"echo foo;"[..]).is_err());
+    }
+}
@@ -1,55 +1,179 @@
 use super::escaped::escaped as escaped_string;
+use super::policy::annotation;
+use super::variable::variable;
 use super::*;
 use crate::ast::*;
 use nom::*;
 
+// Anything a bare word can hold except for expansions and the characters that start blocks and
+// parenthesized commands, so `./build.sh`, `~/bin/run` and `python3.11` all name commands.
 named!(
-    path<String>,
+    name<String>,
+    alt_complete!(map_opt!(quoted, |word: Word| word.as_literal()) | utf8_name)
+);
+
+/// Functions and builtins are looked up by name, so unlike arguments, names must be text.
+fn utf8_name(input: &[u8]) -> IResult<&[u8], String> {
+    let (rest, bytes) = try_parse!(
+        input,
+        verify!(is_not!(" \t\r\n;\"\\{}()$|&<>"), |s: &[u8]| !s.is_empty())
+    );
+    match std::str::from_utf8(bytes) {
+        Ok(name) => IResult::Done(rest, name.to_owned()),
+        Err(_) => {
+            expected(rest, input, "command name is not valid UTF-8");
+            IResult::Error(error_position!(ErrorKind::Custom(0), input))
+        }
+    }
+}
+
+// Inside parentheses, newlines are whitespace like any other.
+named!(pub parenthesized<Command>, ws!(command));
+
+named!(
+    pub substitution<WordPart>,
     map!(
-        recognize!(many1!(alt_complete!(alphanumeric | tag!("/")))),
-        into_string
+        delimited!(
+            tag!("$("),
+            call!(in_parens, parenthesized),
+            expect!("expected `)` to close `$(`", char!(')'))
+        ),
+        |c| WordPart::Command(Box::new(c))
+    )
+);
+
+named!(
+    bare_part<WordPart>,
+    // TODO(shelbyd): Reduce duplication between this and escaped.rs
+    alt_complete!(
+        substitution
+            | variable
+            // Anything that would otherwise end the word or start a redirect can be escaped.
+            | map!(preceded!(char!('\\'), one_of!("\"$<>|&;")), |c| {
+                WordPart::Literal(c.to_string().into())
+            })
+            | value!(
+                WordPart::Literal("\\".into()),
+                terminated!(char!('\\'), not!(line_ending))
+            )
+            | map!(is_not!(" \t\r\n;\"\\{)$|&<>"), |s| WordPart::Literal(
+                into_os_string(s)
+            ))
+            | value!(
+                WordPart::Literal("$".into()),
+                terminated!(char!('$'), not!(char!('(')))
+            )
     )
 );
 
+// A word can't start with `}`, so a block written on one line can end right after a command, nor
+// be a policy annotation, so a loop's condition can end before its body's.
 named!(
-    bare_word<String>,
+    bare_word<Word>,
     map!(
-        // TODO(shelbyd): Reduce duplication between this and escaped.rs
-        escaped_transform!(
-            is_not!(" \t\r\n;\"\\{)"),
-            '\\',
-            alt!(value!(&b"\"", char!('"')) | value!(&b"\\", take!(0)))
+        preceded!(
+            not!(char!('}')),
+            preceded!(not!(annotation), many1!(bare_part))
         ),
-        |s| into_string(&s)
+        Word::new
     )
 );
 
 named!(
-    arg<String>,
-    alt_complete!(delimited!(tag!("\""), escaped_string, tag!("\"")) | bare_word)
+    quoted<Word>,
+    do_parse!(
+        open: tag!("\"") >>
+        word: expect!("unterminated string", at: open, terminated!(escaped_string, tag!("\""))) >>
+        (word)
+    )
 );
 
+named!(pub arg<Word>, alt_complete!(quoted | bare_word));
+
+named!(
+    redirect<Redirect>,
+    alt_complete!(
+        value!(Redirect::StderrToStdout, tag!("2>&1"))
+            | do_parse!(
+                char!('<') >>
+                opt!(space) >>
+                path: expect!("expected a file name after `<`", arg) >>
+                (Redirect::Read(path))
+            )
+            | do_parse!(
+                fd: alt_complete!(
+                    value!(Fd::Stderr, char!('2')) | value!(Fd::Stdout, opt!(char!('1')))
+                ) >>
+                char!('>') >>
+                append: opt!(char!('>')) >>
+                opt!(space) >>
+                path: expect!("expected a file name after `>`", arg) >>
+                (Redirect::Write { fd, path, append: append.is_some() })
+            )
+    )
+);
+
+enum Part {
+    Arg(Word),
+    Redirect(Redirect),
+}
+
+fn build_command(path: String, parts: Vec<Part>) -> Command {
+    let mut args = Vec::new();
+    let mut redirects = Vec::new();
+    for part in parts {
+        match part {
+            Part::Arg(arg) => args.push(arg),
+            Part::Redirect(redirect) => redirects.push(redirect),
+        }
+    }
+    Command::with_words(path, args).redirect(redirects)
+}
+
 named!(
     raw_command<Command>,
     do_parse!(
-        path: path
-            >> args: ws!(separated_list_complete!(multispace, arg))
-            >> (Command::new(path, args))
+        start: here
+            >> path: name
+            >> parts: many0!(alt_complete!(
+                // Redirections may be written right against the previous word, as in `ls>out`.
+                preceded!(opt!(blank), redirect) => { Part::Redirect } |
+                preceded!(blank, arg) => { Part::Arg }
+            ))
+            >> end: here
+            >> opt!(complete!(blank))
+            >> (build_command(path, parts).at(span(start, end)))
     )
 );
 
 named!(pub command<Command>,
-       alt!(
-           raw_command |
-           delimited!(char!('('), command, char!(')'))
-       ));
+alt!(
+    raw_command |
+    delimited!(char!('('), call!(in_parens, parenthesized), char!(')'))
+));
 
 named!(pub command_line<Command>, do_parse!(
     command: command >>
-    char!(';') >>
+    expect!("expected `;` or newline after command", end_of_statement) >>
     (command)
 ));
 
+named!(pub pipeline<Pipeline>, do_parse!(
+    // A command cut off by the end of input isn't a pipeline; leave it to `command` to say so.
+    first: complete!(command) >>
+    rest: many1!(preceded!(
+        complete!(preceded!(opt!(blank), char!('|'))),
+        expect!("expected a command after `|`", preceded!(continuation, command))
+    )) >>
+    (Pipeline(std::iter::once(first).chain(rest).collect()))
+));
+
+named!(pub pipeline_line<Pipeline>, do_parse!(
+    pipeline: pipeline >>
+    expect!("expected `;` or newline after pipeline", end_of_statement) >>
+    (pipeline)
+));
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -78,6 +202,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn underscore() {
+        assert_eq!(
+            command_line(&b"is_even;"[..]),
+            IResult::Done(&b""[..], Command::new("is_even", vec![]))
+        );
+    }
+
     #[test]
     fn empty_string() {
         assert!(command_line(&b";"[..]).is_err());
@@ -113,14 +245,14 @@ mod tests {
 
         #[test]
         fn numeric_argument() {
-            assert_eq!(arg(&b"5"[..]), IResult::Done(&b""[..], "5".to_owned()));
+            assert_eq!(arg(&b"5"[..]), IResult::Done(&b""[..], Word::from("5")));
         }
 
         #[test]
         fn path_argument() {
             assert_eq!(
                 arg(&b"/bin/bash"[..]),
-                IResult::Done(&b""[..], "/bin/bash".to_owned())
+                IResult::Done(&b""[..], Word::from("/bin/bash"))
             );
         }
 
@@ -128,7 +260,7 @@ mod tests {
         fn special_characters() {
             assert_eq!(
                 arg(&b"foo-_.,baz"[..]),
-                IResult::Done(&b""[..], "foo-_.,baz".to_owned())
+                IResult::Done(&b""[..], Word::from("foo-_.,baz"))
             );
         }
 
@@ -136,30 +268,30 @@ mod tests {
         fn semicolon() {
             assert_eq!(
                 arg(&b"foo;"[..]),
-                IResult::Done(&b";"[..], "foo".to_owned())
+                IResult::Done(&b";"[..], Word::from("foo"))
             );
         }
 
         #[test]
         fn empty_string() {
-            assert_eq!(arg(&b""[..]), IResult::Done(&b""[..], "".to_owned()));
+            assert_eq!(arg(&b""[..]), IResult::Done(&b""[..], Word::from("")));
         }
 
         #[test]
         fn empty_quotes() {
-            assert_eq!(arg(&b"\"\""[..]), IResult::Done(&b""[..], "".to_owned()));
+            assert_eq!(arg(&b"\"\""[..]), IResult::Done(&b""[..], Word::from("")));
         }
 
         #[test]
         fn semicolon_wrapped_in_quotes() {
-            assert_eq!(arg(&b"\";\""[..]), IResult::Done(&b""[..], ";".to_owned()));
+            assert_eq!(arg(&b"\";\""[..]), IResult::Done(&b""[..], Word::from(";")));
         }
 
         #[test]
         fn spaces_in_quotes() {
             assert_eq!(
                 arg(&b"\"foo bar baz\""[..]),
-                IResult::Done(&b""[..], "foo bar baz".to_owned())
+                IResult::Done(&b""[..], Word::from("foo bar baz"))
             );
         }
 
@@ -172,7 +304,7 @@ mod tests {
         fn escaped_single_quote() {
             assert_eq!(
                 arg(&b"\"\\\"\""[..]),
-                IResult::Done(&b""[..], "\"".to_owned())
+                IResult::Done(&b""[..], Word::from("\""))
             );
         }
 
@@ -180,7 +312,7 @@ mod tests {
         fn bare_word_and_escaped_quote() {
             assert_eq!(
                 arg(&b"foo\\\"bar"[..]),
-                IResult::Done(&b""[..], "foo\"bar".to_owned())
+                IResult::Done(&b""[..], Word::from("foo\"bar"))
             );
         }
 
@@ -188,7 +320,7 @@ mod tests {
         fn newline_in_bare_word() {
             assert_eq!(
                 arg(&b"foo\nbar"[..]),
-                IResult::Done(&b"\nbar"[..], "foo".to_owned())
+                IResult::Done(&b"\nbar"[..], Word::from("foo"))
             );
         }
 
@@ -196,7 +328,7 @@ mod tests {
         fn escaped_newline_in_bare_word() {
             assert_eq!(
                 arg(&b"foo\\nbar"[..]),
-                IResult::Done(&b""[..], "foo\\nbar".to_owned())
+                IResult::Done(&b""[..], Word::from("foo\\nbar"))
             );
         }
 
@@ -211,12 +343,127 @@ mod tests {
         assert!(command_line(&b"echo foo\"bar\";"[..]).is_err());
     }
 
-    #[test]
-    fn newlines_between_bare_words() {
-        assert_eq!(
-            command_line(&b"echo foo\nbar;"[..]),
-            IResult::Done(&b""[..], Command::new("echo", vec!["foo", "bar"]))
-        );
+    #[cfg(test)]
+    mod lines {
+        use super::*;
+
+        #[test]
+        fn newline_ends_command() {
+            assert_eq!(
+                command_line(&b"echo foo\nbar;"[..]),
+                IResult::Done(&b"bar;"[..], Command::new("echo", vec!["foo"]))
+            );
+        }
+
+        #[test]
+        fn semicolon_is_optional_at_end_of_input() {
+            assert_eq!(
+                command_line(&b"echo foo"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["foo"]))
+            );
+        }
+
+        #[test]
+        fn semicolon_then_newline() {
+            assert_eq!(
+                command_line(&b"echo foo;\n"[..]),
+                IResult::Done(&b"\n"[..], Command::new("echo", vec!["foo"]))
+            );
+        }
+
+        #[test]
+        fn carriage_return() {
+            assert_eq!(
+                command_line(&b"echo foo\r\nbar"[..]),
+                IResult::Done(&b"bar"[..], Command::new("echo", vec!["foo"]))
+            );
+        }
+
+        #[test]
+        fn escaped_newline_continues() {
+            assert_eq!(
+                command_line(&b"echo foo \\\n  bar\n"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["foo", "bar"]))
+            );
+        }
+
+        #[test]
+        fn escaped_newline_after_word_separates() {
+            assert_eq!(
+                command_line(&b"echo foo\\\nbar\n"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["foo", "bar"]))
+            );
+        }
+
+        #[test]
+        fn escaped_newline_at_end_is_incomplete() {
+            assert!(command_line(&b"echo foo \\\n"[..]).is_incomplete());
+        }
+
+        #[test]
+        fn newlines_inside_parens() {
+            assert_eq!(
+                command_line(&b"(\n  echo foo\n  bar\n)\n"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["foo", "bar"]))
+            );
+        }
+
+        #[test]
+        fn newlines_inside_substitution() {
+            assert_eq!(
+                command_line(&b"echo $(ls\n  -l)\n"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::with_words(
+                        "echo",
+                        vec![Word::new(vec![WordPart::Command(Box::new(Command::new(
+                            "ls",
+                            vec!["-l"]
+                        )))])]
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn closing_brace_ends_command() {
+            assert_eq!(
+                command_line(&b"echo foo }"[..]),
+                IResult::Done(&b"}"[..], Command::new("echo", vec!["foo"]))
+            );
+        }
+
+        #[test]
+        fn closing_brace_inside_word() {
+            assert_eq!(
+                command_line(&b"echo a}b\n"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["a}b"]))
+            );
+        }
+
+        #[test]
+        fn policy_name_as_argument() {
+            assert_eq!(
+                command_line(&b"echo @warn @continue-on\n"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("echo", vec!["@warn", "@continue-on"])
+                )
+            );
+        }
+
+        #[test]
+        fn policy_annotation_ends_command() {
+            assert_eq!(
+                command(&b"true @continue { false }"[..]),
+                IResult::Done(&b"@continue { false }"[..], Command::new("true", vec![]))
+            );
+        }
+
+        #[test]
+        fn something_else_after_command() {
+            assert!(command_line(&b"echo foo)"[..]).is_err());
+        }
     }
 
     #[test]
@@ -234,4 +481,523 @@ mod tests {
             IResult::Done(&b""[..], Command::new("echo", vec!["foo", "bar"]))
         );
     }
+
+    #[test]
+    fn variable_argument() {
+        assert_eq!(
+            command_line(&b"echo $foo;"[..]),
+            IResult::Done(
+                &b""[..],
+                Command::with_words(
+                    "echo",
+                    vec![Word::new(vec![WordPart::Variable("foo".into())])]
+                )
+            )
+        );
+    }
+
+    #[cfg(test)]
+    mod variables {
+        use super::*;
+
+        #[test]
+        fn inside_bare_word() {
+            assert_eq!(
+                arg(&b"foo${bar}baz"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![
+                        WordPart::Literal("foo".into()),
+                        WordPart::Variable("bar".into()),
+                        WordPart::Literal("baz".into()),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn stops_at_non_identifier() {
+            assert_eq!(
+                arg(&b"$foo.txt"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![
+                        WordPart::Variable("foo".into()),
+                        WordPart::Literal(".txt".into()),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn script_arguments() {
+            assert_eq!(
+                command_line(&b"deploy $1 \"$2\" $@ --count=$#;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::with_words(
+                        "deploy",
+                        vec![
+                            Word::new(vec![WordPart::Argument(1)]),
+                            Word::new(vec![WordPart::Argument(2)]),
+                            Word::new(vec![WordPart::Arguments]),
+                            Word::new(vec![
+                                WordPart::Literal("--count=".into()),
+                                WordPart::ArgumentCount,
+                            ]),
+                        ]
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn escaped_dollar() {
+            assert_eq!(
+                arg(&b"\\$foo"[..]),
+                IResult::Done(&b""[..], Word::from("$foo"))
+            );
+        }
+
+        #[test]
+        fn escaped_operators() {
+            assert_eq!(
+                arg(&b"a\\>b\\<c\\|d\\&e\\;f"[..]),
+                IResult::Done(&b""[..], Word::from("a>b<c|d&e;f"))
+            );
+        }
+
+        #[test]
+        fn escaped_redirect_is_an_argument() {
+            assert_eq!(
+                command_line(&b"echo \\> out\n"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec![">", "out"]))
+            );
+        }
+
+        #[test]
+        fn trailing_dollar() {
+            assert_eq!(
+                arg(&b"foo$"[..]),
+                IResult::Done(&b""[..], Word::from("foo$"))
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod pipeline {
+        use super::*;
+
+        #[test]
+        fn two_stages() {
+            assert_eq!(
+                pipeline_line(&b"cat foo | wc -l;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Pipeline(vec![
+                        Command::new("cat", vec!["foo"]),
+                        Command::new("wc", vec!["-l"]),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn three_stages_without_spaces() {
+            assert_eq!(
+                pipeline_line(&b"a|b|c;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Pipeline(vec![
+                        Command::new("a", vec![]),
+                        Command::new("b", vec![]),
+                        Command::new("c", vec![]),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn stage_on_next_line() {
+            assert_eq!(
+                pipeline_line(&b"a |\n  b;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Pipeline(vec![Command::new("a", vec![]), Command::new("b", vec![])])
+                )
+            );
+        }
+
+        #[test]
+        fn ends_at_newline() {
+            assert_eq!(
+                pipeline_line(&b"a | b\nc"[..]),
+                IResult::Done(
+                    &b"c"[..],
+                    Pipeline(vec![Command::new("a", vec![]), Command::new("b", vec![])])
+                )
+            );
+        }
+
+        #[test]
+        fn pipe_on_next_line_is_not_a_continuation() {
+            assert!(pipeline_line(&b"a\n| b;"[..]).is_err());
+        }
+
+        #[test]
+        fn trailing_pipe_is_incomplete() {
+            assert!(pipeline_line(&b"a |\n"[..]).is_incomplete());
+        }
+
+        #[test]
+        fn single_command_is_not_pipeline() {
+            assert!(pipeline_line(&b"a;"[..]).is_err());
+        }
+
+        #[test]
+        fn missing_stage() {
+            assert!(pipeline_line(&b"a | ;"[..]).is_err());
+        }
+
+        #[test]
+        fn quoted_pipe_is_argument() {
+            assert_eq!(
+                command_line(&b"echo \"|\";"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["|"]))
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod redirect {
+        use super::*;
+
+        fn write(fd: Fd, path: &str, append: bool) -> Redirect {
+            Redirect::Write {
+                fd,
+                path: path.into(),
+                append,
+            }
+        }
+
+        #[test]
+        fn stdout() {
+            assert_eq!(
+                command_line(&b"ls > out.txt;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("ls", vec![]).redirect(vec![write(Fd::Stdout, "out.txt", false)])
+                )
+            );
+        }
+
+        #[test]
+        fn append_without_spaces() {
+            assert_eq!(
+                command_line(&b"echo foo>>log;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("echo", vec!["foo"]).redirect(vec![write(
+                        Fd::Stdout,
+                        "log",
+                        true
+                    )])
+                )
+            );
+        }
+
+        #[test]
+        fn explicit_stdout_fd() {
+            assert_eq!(
+                command_line(&b"ls 1> out;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("ls", vec![]).redirect(vec![write(Fd::Stdout, "out", false)])
+                )
+            );
+        }
+
+        #[test]
+        fn stdin() {
+            assert_eq!(
+                command_line(&b"wc -l < input;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("wc", vec!["-l"]).redirect(vec![Redirect::Read("input".into())])
+                )
+            );
+        }
+
+        #[test]
+        fn stderr() {
+            assert_eq!(
+                command_line(&b"make 2> err 2>> err.log;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("make", vec![]).redirect(vec![
+                        write(Fd::Stderr, "err", false),
+                        write(Fd::Stderr, "err.log", true),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn stderr_to_stdout_keeps_order() {
+            assert_eq!(
+                command_line(&b"make > out 2>&1;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("make", vec![]).redirect(vec![
+                        write(Fd::Stdout, "out", false),
+                        Redirect::StderrToStdout,
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn args_after_redirect() {
+            assert_eq!(
+                command_line(&b"grep < input foo;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("grep", vec!["foo"])
+                        .redirect(vec![Redirect::Read("input".into())])
+                )
+            );
+        }
+
+        #[test]
+        fn quoted_path_with_variable() {
+            assert_eq!(
+                command_line(&b"ls > \"$dir/out file\";"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("ls", vec![]).redirect(vec![Redirect::Write {
+                        fd: Fd::Stdout,
+                        path: Word::new(vec![
+                            WordPart::Variable("dir".into()),
+                            WordPart::Literal("/out file".into()),
+                        ]),
+                        append: false,
+                    }])
+                )
+            );
+        }
+
+        #[test]
+        fn digit_argument_is_not_redirect() {
+            assert_eq!(
+                command_line(&b"echo 2 > out;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::new("echo", vec!["2"]).redirect(vec![write(Fd::Stdout, "out", false)])
+                )
+            );
+        }
+
+        #[test]
+        fn missing_path() {
+            assert!(command_line(&b"ls >;"[..]).is_err());
+        }
+
+        #[test]
+        fn quoted_angle_brackets_are_arguments() {
+            assert_eq!(
+                command_line(&b"echo \"<>\";"[..]),
+                IResult::Done(&b""[..], Command::new("echo", vec!["<>"]))
+            );
+        }
+
+        #[test]
+        fn in_pipeline() {
+            assert_eq!(
+                pipeline_line(&b"cat < in | sort > out;"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Pipeline(vec![
+                        Command::new("cat", vec![]).redirect(vec![Redirect::Read("in".into())]),
+                        Command::new("sort", vec![]).redirect(vec![write(
+                            Fd::Stdout,
+                            "out",
+                            false
+                        )]),
+                    ])
+                )
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod substitution {
+        use super::*;
+
+        fn sub(command: Command) -> WordPart {
+            WordPart::Command(Box::new(command))
+        }
+
+        #[test]
+        fn whole_argument() {
+            assert_eq!(
+                command_line(&b"git checkout $(git rev-parse HEAD);"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Command::with_words(
+                        "git",
+                        vec![
+                            "checkout".into(),
+                            Word::new(vec![sub(Command::new("git", vec!["rev-parse", "HEAD"]))]),
+                        ]
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn inside_bare_word() {
+            assert_eq!(
+                arg(&b"v$(cat VERSION).tar"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![
+                        WordPart::Literal("v".into()),
+                        sub(Command::new("cat", vec!["VERSION"])),
+                        WordPart::Literal(".tar".into()),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn inside_quotes() {
+            assert_eq!(
+                arg(&b"\"today is $( date +%A )\""[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![
+                        WordPart::Literal("today is ".into()),
+                        sub(Command::new("date", vec!["+%A"])),
+                    ])
+                )
+            );
+        }
+
+        #[test]
+        fn nested() {
+            assert_eq!(
+                arg(&b"$(basename $(pwd))"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![sub(Command::with_words(
+                        "basename",
+                        vec![Word::new(vec![sub(Command::new("pwd", vec![]))])]
+                    ))])
+                )
+            );
+        }
+
+        #[test]
+        fn with_variable_argument() {
+            assert_eq!(
+                arg(&b"$(cat $file)"[..]),
+                IResult::Done(
+                    &b""[..],
+                    Word::new(vec![sub(Command::with_words(
+                        "cat",
+                        vec![Word::new(vec![WordPart::Variable("file".into())])]
+                    ))])
+                )
+            );
+        }
+
+        #[test]
+        fn unterminated() {
+            assert!(!command_line(&b"echo $(date;"[..]).is_done());
+        }
+    }
+
+    #[cfg(test)]
+    mod names {
+        use super::*;
+
+        #[test]
+        fn relative_script() {
+            assert_eq!(
+                command_line(&b"./build.sh arg;"[..]),
+                IResult::Done(&b""[..], Command::new("./build.sh", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn parent_dir() {
+            assert_eq!(
+                command_line(&b"../tool arg;"[..]),
+                IResult::Done(&b""[..], Command::new("../tool", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn home_dir() {
+            assert_eq!(
+                command_line(&b"~/bin/run arg;"[..]),
+                IResult::Done(&b""[..], Command::new("~/bin/run", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn hyphen() {
+            assert_eq!(
+                command_line(&b"docker-compose arg;"[..]),
+                IResult::Done(&b""[..], Command::new("docker-compose", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn dots() {
+            assert_eq!(
+                command_line(&b"python3.11 arg;"[..]),
+                IResult::Done(&b""[..], Command::new("python3.11", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn target_triple() {
+            assert_eq!(
+                command_line(&b"x86_64-linux-gnu-gcc arg;"[..]),
+                IResult::Done(&b""[..], Command::new("x86_64-linux-gnu-gcc", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn quoted() {
+            assert_eq!(
+                command_line(&b"\"my tool\" arg;"[..]),
+                IResult::Done(&b""[..], Command::new("my tool", vec!["arg"]))
+            );
+        }
+
+        #[test]
+        fn quoted_with_variable() {
+            assert!(command_line(&b"\"$tool\" arg;"[..]).is_err());
+        }
+
+        #[test]
+        fn variable() {
+            assert!(command_line(&b"$tool arg;"[..]).is_err());
+        }
+
+        #[test]
+        fn closing_brace() {
+            assert!(command_line(&b"} arg;"[..]).is_err());
+        }
+
+        #[test]
+        fn parens_are_not_part_of_name() {
+            assert_eq!(
+                command(&b"(ls)"[..]),
+                IResult::Done(&b""[..], Command::new("ls", vec![]))
+            );
+        }
+    }
 }
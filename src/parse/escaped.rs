@@ -1,16 +1,25 @@
+use super::command::substitution;
+use super::variable::variable;
 use super::*;
 
-named!(pub escaped<String>, alt_complete!(
-    value!(String::new(), peek!(tag!("\""))) |
-    map!(escaped_transform!(
-        is_not!("\\\""),
-        '\\',
-        alt!(
-            value!(&b"\""[..], char!('"')) |
-            value!(&b"\\"[..], take!(0))
-        )),
-    |s| into_string(&s))
-));
+named!(
+    quoted_part<WordPart>,
+    alt_complete!(
+        substitution
+            | variable
+            | map!(preceded!(char!('\\'), alt!(char!('"') | char!('$'))), |c| {
+                WordPart::Literal(c.to_string().into())
+            })
+            | value!(WordPart::Literal("\\".into()), char!('\\'))
+            | map!(is_not!("\\\"$"), |s| WordPart::Literal(into_os_string(s)))
+            | value!(
+                WordPart::Literal("$".into()),
+                terminated!(char!('$'), not!(char!('(')))
+            )
+    )
+);
+
+named!(pub escaped<Word>, map!(many0!(quoted_part), Word::new));
 
 #[cfg(test)]
 mod tests {
@@ -18,14 +27,14 @@ mod tests {
 
     #[test]
     fn empty_string() {
-        assert_eq!(escaped(&b""[..]), IResult::Done(&b""[..], "".to_owned()));
+        assert_eq!(escaped(&b""[..]), IResult::Done(&b""[..], Word::from("")));
     }
 
     #[test]
     fn no_problem_characters() {
         assert_eq!(
             escaped(&b"foo"[..]),
-            IResult::Done(&b""[..], "foo".to_owned())
+            IResult::Done(&b""[..], Word::from("foo"))
         );
     }
 
@@ -33,7 +42,7 @@ mod tests {
     fn unescaped_terminal() {
         assert_eq!(
             escaped(&b"f\"oo"[..]),
-            IResult::Done(&b"\"oo"[..], "f".to_owned())
+            IResult::Done(&b"\"oo"[..], Word::from("f"))
         );
     }
 
@@ -41,7 +50,7 @@ mod tests {
     fn escaped_terminal() {
         assert_eq!(
             escaped(&b"f\\\"oo"[..]),
-            IResult::Done(&b""[..], "f\"oo".to_owned())
+            IResult::Done(&b""[..], Word::from("f\"oo"))
         );
     }
 
@@ -49,7 +58,7 @@ mod tests {
     fn multiple_escaped_terminals() {
         assert_eq!(
             escaped(&b"f\\\"o\\\"o"[..]),
-            IResult::Done(&b""[..], "f\"o\"o".to_owned())
+            IResult::Done(&b""[..], Word::from("f\"o\"o"))
         );
     }
 
@@ -57,7 +66,7 @@ mod tests {
     fn escaped_after_unescaped() {
         assert_eq!(
             escaped(&b"f\"o\\\"o"[..]),
-            IResult::Done(&b"\"o\\\"o"[..], "f".to_owned())
+            IResult::Done(&b"\"o\\\"o"[..], Word::from("f"))
         );
     }
 
@@ -65,7 +74,7 @@ mod tests {
     fn immediate_terminal() {
         assert_eq!(
             escaped(&b"\""[..]),
-            IResult::Done(&b"\""[..], "".to_owned())
+            IResult::Done(&b"\""[..], Word::from(""))
         );
     }
 
@@ -73,7 +82,7 @@ mod tests {
     fn newline() {
         assert_eq!(
             escaped(&b"\\n"[..]),
-            IResult::Done(&b""[..], "\\n".to_owned())
+            IResult::Done(&b""[..], Word::from("\\n"))
         );
     }
 
@@ -81,7 +90,7 @@ mod tests {
     fn other_escapes() {
         assert_eq!(
             escaped(&b"\\t\\r\\'"[..]),
-            IResult::Done(&b""[..], "\\t\\r\\'".to_owned())
+            IResult::Done(&b""[..], Word::from("\\t\\r\\'"))
         );
     }
 
@@ -89,7 +98,38 @@ mod tests {
     fn starts_with_escaped_terminal() {
         assert_eq!(
             escaped(&b"\\\""[..]),
-            IResult::Done(&b""[..], "\"".to_owned())
+            IResult::Done(&b""[..], Word::from("\""))
+        );
+    }
+
+    #[test]
+    fn variable() {
+        assert_eq!(
+            escaped(&b"hello $name!"[..]),
+            IResult::Done(
+                &b""[..],
+                Word::new(vec![
+                    WordPart::Literal("hello ".into()),
+                    WordPart::Variable("name".into()),
+                    WordPart::Literal("!".into()),
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn escaped_dollar() {
+        assert_eq!(
+            escaped(&b"\\$name"[..]),
+            IResult::Done(&b""[..], Word::from("$name"))
+        );
+    }
+
+    #[test]
+    fn lone_dollar() {
+        assert_eq!(
+            escaped(&b"cost: $ 5"[..]),
+            IResult::Done(&b""[..], Word::from("cost: $ 5"))
         );
     }
 }
@@ -0,0 +1,162 @@
+use super::command::{arg, parenthesized};
+use super::loop_control::label;
+use super::variable::{identifier, variable};
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+named!(
+    bound<Word>,
+    alt_complete!(
+        map!(variable, |v| Word::new(vec![v]))
+            | map!(recognize!(preceded!(opt!(char!('-')), digit)), |s| {
+                Word::from(into_string(s))
+            })
+    )
+);
+
+named!(
+    range<Items>,
+    do_parse!(
+        start: bound >>
+        tag!("..") >>
+        inclusive: opt!(char!('=')) >>
+        end: bound >>
+        (Items::Range { start, end, inclusive: inclusive.is_some() })
+    )
+);
+
+named!(
+    items<Items>,
+    alt_complete!(
+        delimited!(char!('('), call!(in_parens, parenthesized), char!(')')) => { Items::Lines } |
+        range |
+        separated_list_complete!(blank, arg) => { Items::Words }
+    )
+);
+
+named!(pub for_stmt<For>, do_parse!(
+    start: here >>
+    label: label >>
+    call!(keyword, "for") >>
+    complete!(multispace) >>
+    variable: expect!("expected a variable name after `for`", identifier) >>
+    expect!("expected `in` after the variable", ws!(call!(keyword, "in"))) >>
+    items: ws!(items) >>
+    block: expect!("expected a statement after the items", ast) >>
+    end: here >>
+    ({
+        let stmt = For::new(variable, items, block).at(span(start, end));
+        match label {
+            Some(label) => stmt.labelled(label),
+            None => stmt,
+        }
+    })
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            for_stmt(&b"for x in a b c {}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new(
+                    "x",
+                    Items::Words(vec!["a".into(), "b".into(), "c".into()]),
+                    AST::Block(Block::new(vec![]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn no_words() {
+        assert_eq!(
+            for_stmt(&b"for x in {}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new("x", Items::Words(vec![]), AST::Block(Block::new(vec![])))
+            )
+        );
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(
+            for_stmt(&b"for i in 0..10 {}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new(
+                    "i",
+                    Items::Range {
+                        start: "0".into(),
+                        end: "10".into(),
+                        inclusive: false,
+                    },
+                    AST::Block(Block::new(vec![]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn inclusive_range_with_variable() {
+        assert_eq!(
+            for_stmt(&b"for i in -1..=$n {}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new(
+                    "i",
+                    Items::Range {
+                        start: "-1".into(),
+                        end: Word::new(vec![WordPart::Variable("n".into())]),
+                        inclusive: true,
+                    },
+                    AST::Block(Block::new(vec![]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn command_lines() {
+        assert_eq!(
+            for_stmt(&b"for line in (ls -1) {}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new(
+                    "line",
+                    Items::Lines(Command::new("ls", vec!["-1"])),
+                    AST::Block(Block::new(vec![]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn body_uses_variable() {
+        assert_eq!(
+            for_stmt(&b"for x in a {\n  echo $x;\n}"[..]),
+            IResult::Done(
+                &b""[..],
+                For::new(
+                    "x",
+                    Items::Words(vec!["a".into()]),
+                    AST::Block(Block::new(vec![AST::Command(Command::with_words(
+                        "echo",
+                        vec![Word::new(vec![WordPart::Variable("x".into())])]
+                    ))]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn requires_space_after_keyword() {
+        assert!(!for_stmt(&b"format x in a {}"[..]).is_done());
+    }
+}
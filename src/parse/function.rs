@@ -0,0 +1,128 @@
+use super::command::arg;
+use super::variable::identifier;
+use super::*;
+use crate::ast::*;
+use nom::*;
+use std::rc::Rc;
+
+named!(
+    params<Vec<String>>,
+    delimited!(
+        char!('('),
+        ws!(separated_list_complete!(ws!(char!(',')), identifier)),
+        expect!("expected `)` after the parameters", char!(')'))
+    )
+);
+
+named!(pub function<Rc<Function>>, do_parse!(
+    start: here >>
+    call!(keyword, "fn") >>
+    complete!(multispace) >>
+    name: expect!("expected a function name after `fn`", identifier) >>
+    params: expect!("expected `(` after the function name", ws!(params)) >>
+    body: expect!("expected `{` after the parameters", block) >>
+    end: here >>
+    (Rc::new(Function::new(name, params, body).at(span(start, end))))
+));
+
+named!(pub return_stmt<AST>, do_parse!(
+    start: here >>
+    call!(keyword, "return") >>
+    value: opt!(complete!(preceded!(blank, arg))) >>
+    end: here >>
+    expect!("expected `;` or newline after return", end_of_statement) >>
+    (AST::Return(value, span(start, end)))
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_params() {
+        assert_eq!(
+            function(&b"fn greet() {}"[..]),
+            IResult::Done(
+                &b""[..],
+                Rc::new(Function::new("greet", vec![], Block::new(vec![])))
+            )
+        );
+    }
+
+    #[test]
+    fn params_and_body() {
+        assert_eq!(
+            function(&b"fn deploy(env, tag) {\n  echo $env;\n}"[..]),
+            IResult::Done(
+                &b""[..],
+                Rc::new(Function::new(
+                    "deploy",
+                    vec!["env", "tag"],
+                    Block::new(vec![AST::Command(Command::with_words(
+                        "echo",
+                        vec![Word::new(vec![WordPart::Variable("env".into())])]
+                    ))])
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn params_without_spaces() {
+        assert_eq!(
+            function(&b"fn f(a,b){}"[..]),
+            IResult::Done(
+                &b""[..],
+                Rc::new(Function::new("f", vec!["a", "b"], Block::new(vec![])))
+            )
+        );
+    }
+
+    #[test]
+    fn requires_space_after_keyword() {
+        assert!(!function(&b"fnord() {}"[..]).is_done());
+    }
+
+    #[test]
+    fn requires_block() {
+        assert!(!function(&b"fn f() echo;"[..]).is_done());
+    }
+
+    #[test]
+    fn return_status() {
+        assert_eq!(
+            return_stmt(&b"return 1;"[..]),
+            IResult::Done(
+                &b""[..],
+                AST::Return(Some(Word::from("1")), Span::default())
+            )
+        );
+    }
+
+    #[test]
+    fn return_variable() {
+        assert_eq!(
+            return_stmt(&b"return $status ;"[..]),
+            IResult::Done(
+                &b""[..],
+                AST::Return(
+                    Some(Word::new(vec![WordPart::Variable("status".into())])),
+                    Span::default()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn bare_return() {
+        assert_eq!(
+            return_stmt(&b"return;"[..]),
+            IResult::Done(&b""[..], AST::Return(None, Span::default()))
+        );
+    }
+
+    #[test]
+    fn command_starting_with_return() {
+        assert!(!return_stmt(&b"returns;"[..]).is_done());
+    }
+}
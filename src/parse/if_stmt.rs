@@ -1,24 +1,50 @@
+use super::policy::annotation;
 use super::*;
 use crate::ast::*;
 use nom::*;
 
 named!(
-    pub block<Vec<AST>>,
-    do_parse!(tag!("{") >> tree: parse_tree >> tag!("}") >> (tree))
+    braces<Block>,
+    do_parse!(
+        start: here >>
+        tag!("{") >>
+        tree: parse_tree >>
+        expect!("expected `}`", tag!("}")) >>
+        end: here >>
+        (Block::new(tree).at(span(start, end)))
+    )
+);
+
+// A block, optionally preceded by the failure policy it runs with, as in `@continue { ... }`.
+named!(
+    pub block<Block>,
+    alt!(
+        braces |
+        do_parse!(
+            policy: annotation >>
+            block: braces >>
+            (block.with_policy(policy))
+        )
+    )
 );
 
 named!(
     els<Option<AST>>,
-    opt!(complete!(ws!(preceded!(tag!("else"), ast))))
+    opt!(preceded!(
+        complete!(ws!(call!(keyword, "else"))),
+        expect!("expected a statement after `else`", ast)
+    ))
 );
 
 named!(pub if_stmt<Conditional>, do_parse!(
-        tag!("if") >>
-        command: ws!(command) >>
-        block: ast >>
-        els: els >>
-        (Conditional::new(command, block, els))
-      ));
+    start: here >>
+    call!(keyword, "if") >>
+    predicate: expect!("expected a condition after `if`", ws!(predicate)) >>
+    block: expect!("expected a statement after the condition", ast) >>
+    els: els >>
+    end: here >>
+    (Conditional::new(predicate, block, els).at(span(start, end)))
+));
 
 #[cfg(test)]
 mod tests {
@@ -32,7 +58,7 @@ mod tests {
                 &b""[..],
                 Conditional::new(
                     Command::new("true", vec![]),
-                    AST::Block(Block(vec![])),
+                    AST::Block(Block::new(vec![])),
                     None
                 )
             )
@@ -52,7 +78,7 @@ mod tests {
                 &b""[..],
                 Conditional::new(
                     Command::new("true", vec![]),
-                    AST::Block(Block(vec![
+                    AST::Block(Block::new(vec![
                         AST::Command(Command::new("echo", vec!["foo"])),
                         AST::Command(Command::new("echo", vec!["bar"])),
                     ])),
@@ -62,6 +88,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn statements_without_semicolons() {
+        assert_eq!(
+            if_stmt(
+                &b"if true {
+                      echo foo
+                      echo bar
+                    } else { echo baz }"[..]
+            ),
+            IResult::Done(
+                &b""[..],
+                Conditional::new(
+                    Command::new("true", vec![]),
+                    AST::Block(Block::new(vec![
+                        AST::Command(Command::new("echo", vec!["foo"])),
+                        AST::Command(Command::new("echo", vec!["bar"])),
+                    ])),
+                    Some(AST::Block(Block::new(vec![AST::Command(Command::new(
+                        "echo",
+                        vec!["baz"]
+                    ))]))),
+                )
+            )
+        );
+    }
+
     #[test]
     fn empty_else_block() {
         assert_eq!(
@@ -70,8 +122,8 @@ mod tests {
                 &b""[..],
                 Conditional::new(
                     Command::new("false", vec![]),
-                    AST::Block(Block(vec![])),
-                    Some(AST::Block(Block(vec![])))
+                    AST::Block(Block::new(vec![])),
+                    Some(AST::Block(Block::new(vec![])))
                 )
             )
         );
@@ -85,14 +137,50 @@ mod tests {
                 &b""[..],
                 Conditional::new(
                     Command::new("false", vec![]),
-                    AST::Block(Block(vec![])),
+                    AST::Block(Block::new(vec![])),
                     Some(AST::If(Conditional::new(
                         Command::new("false", vec![]),
-                        AST::Block(Block(vec![])),
+                        AST::Block(Block::new(vec![])),
                         None
                     )))
                 )
             )
         );
     }
+
+    #[test]
+    fn pipeline_predicate() {
+        assert_eq!(
+            if_stmt(&b"if ls | grep -q foo {}"[..]),
+            IResult::Done(
+                &b""[..],
+                Conditional::new(
+                    Predicate::Pipeline(Pipeline(vec![
+                        Command::new("ls", vec![]),
+                        Command::new("grep", vec!["-q", "foo"]),
+                    ])),
+                    AST::Block(Block::new(vec![])),
+                    None
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn boolean_predicate() {
+        assert_eq!(
+            if_stmt(&b"if test -f foo && !test -d bar {}"[..]),
+            IResult::Done(
+                &b""[..],
+                Conditional::new(
+                    Predicate::and(
+                        Command::new("test", vec!["-f", "foo"]).into(),
+                        Predicate::not(Command::new("test", vec!["-d", "bar"]).into()),
+                    ),
+                    AST::Block(Block::new(vec![])),
+                    None
+                )
+            )
+        );
+    }
 }
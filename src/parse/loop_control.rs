@@ -0,0 +1,66 @@
+use super::variable::identifier;
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+// The name before a loop, as in `outer: while`.
+named!(pub label<Option<String>>, opt!(complete!(terminated!(
+    identifier,
+    pair!(char!(':'), opt!(complete!(blank)))
+))));
+
+named!(pub break_stmt<AST>, do_parse!(
+    start: here >>
+    call!(keyword, "break") >>
+    label: opt!(complete!(preceded!(blank, identifier))) >>
+    end: here >>
+    expect!("expected `;` or newline after break", end_of_statement) >>
+    (AST::Break(label, span(start, end)))
+));
+
+named!(pub continue_stmt<AST>, do_parse!(
+    start: here >>
+    call!(keyword, "continue") >>
+    label: opt!(complete!(preceded!(blank, identifier))) >>
+    end: here >>
+    expect!("expected `;` or newline after continue", end_of_statement) >>
+    (AST::Continue(label, span(start, end)))
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_break() {
+        assert_eq!(
+            break_stmt(&b"break\n"[..]),
+            IResult::Done(&b""[..], AST::Break(None, Span::default()))
+        );
+    }
+
+    #[test]
+    fn labelled_continue() {
+        assert_eq!(
+            continue_stmt(&b"continue outer;"[..]),
+            IResult::Done(
+                &b""[..],
+                AST::Continue(Some("outer".to_owned()), Span::default())
+            )
+        );
+    }
+
+    #[test]
+    fn command_starting_with_break() {
+        assert!(!break_stmt(&b"breakpoint\n"[..]).is_done());
+    }
+
+    #[test]
+    fn loop_label() {
+        assert_eq!(
+            label(&b"outer: while"[..]),
+            IResult::Done(&b"while"[..], Some("outer".to_owned()))
+        );
+        assert_eq!(label(&b"while"[..]), IResult::Done(&b"while"[..], None));
+    }
+}
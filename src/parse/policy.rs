@@ -0,0 +1,54 @@
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+named!(pub failure_policy<FailurePolicy>, preceded!(
+    char!('@'),
+    alt_complete!(
+        value!(FailurePolicy::Strict, call!(keyword, "strict")) |
+        value!(FailurePolicy::Continue, call!(keyword, "continue")) |
+        value!(FailurePolicy::Warn, call!(keyword, "warn"))
+    )
+));
+
+// The policy before a block, as in `@continue {`.
+named!(pub annotation<FailurePolicy>, terminated!(
+    failure_policy,
+    preceded!(opt!(complete!(blank)), peek!(complete!(char!('{'))))
+));
+
+named!(pub pragma<FailurePolicy>, terminated!(
+    failure_policy,
+    expect!("expected `;` or newline after the policy", end_of_statement)
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_policy() {
+        assert_eq!(
+            pragma(&b"@strict\n"[..]),
+            IResult::Done(&b""[..], FailurePolicy::Strict)
+        );
+        assert_eq!(
+            pragma(&b"@continue;"[..]),
+            IResult::Done(&b""[..], FailurePolicy::Continue)
+        );
+        assert_eq!(
+            pragma(&b"@warn"[..]),
+            IResult::Done(&b""[..], FailurePolicy::Warn)
+        );
+    }
+
+    #[test]
+    fn unknown_policy() {
+        assert!(pragma(&b"@lenient\n"[..]).is_err());
+    }
+
+    #[test]
+    fn longer_name() {
+        assert!(pragma(&b"@warnings\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use super::command::{command, pipeline};
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+named!(
+    atom<Predicate>,
+    preceded!(
+        opt!(blank),
+        alt!(
+            preceded!(
+                char!('!'),
+                expect!("expected a command after `!`", preceded!(continuation, atom))
+            ) => { Predicate::not } |
+            delimited!(
+                char!('('),
+                call!(in_parens, predicate),
+                expect!("expected `)`", preceded!(opt!(blank), char!(')')))
+            ) |
+            pipeline => { Predicate::Pipeline } |
+            command => { Predicate::Command }
+        )
+    )
+);
+
+// `&&` binds tighter than `||`, so `a || b && c` is `a || (b && c)`.
+named!(
+    conjunction<Predicate>,
+    do_parse!(
+        first: atom >>
+        rest: many0!(preceded!(
+            complete!(preceded!(opt!(blank), tag!("&&"))),
+            expect!("expected a command after `&&`", preceded!(continuation, atom))
+        )) >>
+        (rest.into_iter().fold(first, Predicate::and))
+    )
+);
+
+named!(
+    pub predicate<Predicate>,
+    do_parse!(
+        first: conjunction >>
+        rest: many0!(preceded!(
+            complete!(preceded!(opt!(blank), tag!("||"))),
+            expect!("expected a command after `||`", preceded!(continuation, conjunction))
+        )) >>
+        (rest.into_iter().fold(first, Predicate::or))
+    )
+);
+
+named!(pub predicate_line<Predicate>, do_parse!(
+    predicate: predicate >>
+    expect!("expected `;` or newline after command", end_of_statement) >>
+    (predicate)
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(s: &str) -> Predicate {
+        Predicate::Command(Command::new(s, vec![]))
+    }
+
+    #[test]
+    fn single_command() {
+        assert_eq!(
+            predicate(&b"test -f foo"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::Command(Command::new("test", vec!["-f", "foo"]))
+            )
+        );
+    }
+
+    #[test]
+    fn and() {
+        assert_eq!(
+            predicate(&b"a && b"[..]),
+            IResult::Done(&b""[..], Predicate::and(cmd("a"), cmd("b")))
+        );
+    }
+
+    #[test]
+    fn or() {
+        assert_eq!(
+            predicate(&b"a || b"[..]),
+            IResult::Done(&b""[..], Predicate::or(cmd("a"), cmd("b")))
+        );
+    }
+
+    #[test]
+    fn not() {
+        assert_eq!(
+            predicate(&b"!a"[..]),
+            IResult::Done(&b""[..], Predicate::not(cmd("a")))
+        );
+    }
+
+    #[test]
+    fn not_with_space() {
+        assert_eq!(
+            predicate(&b"! a"[..]),
+            IResult::Done(&b""[..], Predicate::not(cmd("a")))
+        );
+    }
+
+    #[test]
+    fn and_is_left_associative() {
+        assert_eq!(
+            predicate(&b"a && b && c"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::and(Predicate::and(cmd("a"), cmd("b")), cmd("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            predicate(&b"a || b && c"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::or(cmd("a"), Predicate::and(cmd("b"), cmd("c")))
+            )
+        );
+    }
+
+    #[test]
+    fn mixed() {
+        assert_eq!(
+            predicate(&b"a && !b || c"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::or(Predicate::and(cmd("a"), Predicate::not(cmd("b"))), cmd("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            predicate(&b"(a || b) && c"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::and(Predicate::or(cmd("a"), cmd("b")), cmd("c"))
+            )
+        );
+    }
+
+    fn piped(a: &str, b: &str) -> Predicate {
+        Predicate::Pipeline(Pipeline(vec![
+            Command::new(a, vec![]),
+            Command::new(b, vec![]),
+        ]))
+    }
+
+    #[test]
+    fn pipelines_as_operands() {
+        assert_eq!(
+            predicate(&b"a | b && c || d | e"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::or(Predicate::and(piped("a", "b"), cmd("c")), piped("d", "e"))
+            )
+        );
+    }
+
+    #[test]
+    fn not_applies_to_whole_pipeline() {
+        assert_eq!(
+            predicate(&b"!a | b"[..]),
+            IResult::Done(&b""[..], Predicate::not(piped("a", "b")))
+        );
+    }
+
+    #[test]
+    fn not_applies_to_group() {
+        assert_eq!(
+            predicate(&b"!(a && b)"[..]),
+            IResult::Done(&b""[..], Predicate::not(Predicate::and(cmd("a"), cmd("b"))))
+        );
+    }
+
+    #[test]
+    fn with_arguments() {
+        assert_eq!(
+            predicate(&b"test -d build || mkdir build"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::or(
+                    Predicate::Command(Command::new("test", vec!["-d", "build"])),
+                    Predicate::Command(Command::new("mkdir", vec!["build"]))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn without_spaces() {
+        assert_eq!(
+            predicate(&b"a&&b"[..]),
+            IResult::Done(&b""[..], Predicate::and(cmd("a"), cmd("b")))
+        );
+    }
+
+    #[test]
+    fn line() {
+        assert_eq!(
+            predicate_line(&b"a && b;"[..]),
+            IResult::Done(&b""[..], Predicate::and(cmd("a"), cmd("b")))
+        );
+    }
+
+    #[test]
+    fn operand_on_next_line() {
+        assert_eq!(
+            predicate_line(&b"a &&\n  b ||\n  c\n"[..]),
+            IResult::Done(
+                &b""[..],
+                Predicate::or(Predicate::and(cmd("a"), cmd("b")), cmd("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn newlines_inside_group() {
+        assert_eq!(
+            predicate_line(&b"(a\n  && b)\n"[..]),
+            IResult::Done(&b""[..], Predicate::and(cmd("a"), cmd("b")))
+        );
+    }
+
+    #[test]
+    fn operator_on_next_line_is_not_a_continuation() {
+        assert_eq!(
+            predicate_line(&b"a\n&& b;"[..]),
+            IResult::Done(&b"&& b;"[..], cmd("a"))
+        );
+    }
+
+    #[test]
+    fn trailing_operator_is_incomplete() {
+        assert!(predicate_line(&b"a &&\n"[..]).is_incomplete());
+        assert!(predicate_line(&b"a ||"[..]).is_incomplete());
+    }
+
+    #[test]
+    fn dangling_operator() {
+        assert!(predicate_line(&b"a &&;"[..]).is_err());
+    }
+
+    #[test]
+    fn unclosed_group() {
+        assert!(!predicate_line(&b"(a || b;"[..]).is_done());
+    }
+}
@@ -0,0 +1,66 @@
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+named!(pub subshell<Subshell>, do_parse!(
+    start: here >>
+    fork: alt_complete!(
+        value!(false, call!(keyword, "subshell")) |
+        value!(true, call!(keyword, "fork"))
+    ) >>
+    body: expect!("expected `{`", ws!(block)) >>
+    end: here >>
+    (Subshell::new(body, fork).at(span(start, end)))
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            subshell(&b"subshell {}"[..]),
+            IResult::Done(&b""[..], Subshell::new(Block::new(vec![]), false))
+        );
+    }
+
+    #[test]
+    fn with_statements() {
+        assert_eq!(
+            subshell(&b"subshell { let foo = bar; echo $foo; }"[..]),
+            IResult::Done(
+                &b""[..],
+                Subshell::new(
+                    Block::new(vec![
+                        AST::Let(Assignment::new("foo", "bar".into())),
+                        AST::Command(Command::with_words(
+                            "echo",
+                            vec![Word::new(vec![WordPart::Variable("foo".into())])]
+                        )),
+                    ]),
+                    false
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn fork() {
+        assert_eq!(
+            subshell(&b"fork { echo; }"[..]),
+            IResult::Done(
+                &b""[..],
+                Subshell::new(
+                    Block::new(vec![AST::Command(Command::new("echo", vec![]))]),
+                    true
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn command_starting_with_keyword() {
+        assert!(subshell(&b"forklift;"[..]).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use super::if_stmt::block;
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+named!(pub try_stmt<Try>, do_parse!(
+    start: here >>
+    call!(keyword, "try") >>
+    body: expect!("expected `{` after `try`", ws!(block)) >>
+    expect!("expected `catch` after the `try` block", call!(keyword, "catch")) >>
+    handler: expect!("expected `{` after `catch`", ws!(block)) >>
+    end: here >>
+    (Try::new(body, handler).at(span(start, end)))
+));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            try_stmt(&b"try {} catch {}"[..]),
+            IResult::Done(&b""[..], Try::new(Block::new(vec![]), Block::new(vec![])))
+        );
+    }
+
+    #[test]
+    fn catch_on_next_line() {
+        assert_eq!(
+            try_stmt(&b"try {\n  make\n}\ncatch {\n  echo $status\n}"[..]),
+            IResult::Done(
+                &b""[..],
+                Try::new(
+                    Block::new(vec![AST::Command(Command::new("make", vec![]))]),
+                    Block::new(vec![AST::Command(Command::with_words(
+                        "echo",
+                        vec![Word::new(vec![WordPart::Variable("status".into())])]
+                    ))]),
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn missing_catch() {
+        assert!(try_stmt(&b"try { make }\necho done\n"[..]).is_err());
+    }
+
+    #[test]
+    fn command_starting_with_keyword() {
+        assert!(try_stmt(&b"trying;"[..]).is_err());
+    }
+}
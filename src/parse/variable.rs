@@ -0,0 +1,135 @@
+use super::*;
+use crate::ast::*;
+use nom::*;
+
+pub(super) fn is_identifier_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// A name ends where the input does, rather than waiting for more input that might continue it.
+fn name(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let len = input.iter().take_while(|&&c| is_identifier_char(c)).count();
+    if len == 0 || input[0].is_ascii_digit() {
+        return IResult::Error(error_position!(ErrorKind::TakeWhile1, input));
+    }
+    IResult::Done(&input[len..], &input[..len])
+}
+
+named!(pub identifier<String>, map!(name, into_string));
+
+named!(
+    argument_index<usize>,
+    map_opt!(digit, |n| std::str::from_utf8(n)
+        .ok()
+        .and_then(|n| n.parse().ok()))
+);
+
+named!(
+    pub variable<WordPart>,
+    preceded!(
+        char!('$'),
+        alt_complete!(
+            delimited!(char!('{'), identifier, char!('}')) => { WordPart::Variable } |
+            identifier => { WordPart::Variable } |
+            delimited!(char!('{'), argument_index, char!('}')) => { WordPart::Argument } |
+            // Like other shells, only one digit is read without braces, so `$10` is `${1}0`.
+            map_opt!(take!(1), |d: &[u8]| (d[0] as char).to_digit(10)) => {
+                |d| WordPart::Argument(d as usize)
+            } |
+            value!(WordPart::Arguments, char!('@')) |
+            value!(WordPart::ArgumentCount, char!('#'))
+        )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_with_underscore_and_digits() {
+        assert_eq!(
+            identifier(&b"foo_bar2 "[..]),
+            IResult::Done(&b" "[..], "foo_bar2".to_owned())
+        );
+    }
+
+    #[test]
+    fn identifier_cannot_start_with_digit() {
+        assert!(identifier(&b"2foo"[..]).is_err());
+    }
+
+    #[test]
+    fn bare_variable() {
+        assert_eq!(
+            variable(&b"$foo"[..]),
+            IResult::Done(&b""[..], WordPart::Variable("foo".to_owned()))
+        );
+    }
+
+    #[test]
+    fn braced_variable() {
+        assert_eq!(
+            variable(&b"${foo}bar"[..]),
+            IResult::Done(&b"bar"[..], WordPart::Variable("foo".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unterminated_brace() {
+        assert!(variable(&b"${foo"[..]).is_err());
+    }
+
+    #[test]
+    fn positional_argument() {
+        assert_eq!(
+            variable(&b"$1"[..]),
+            IResult::Done(&b""[..], WordPart::Argument(1))
+        );
+    }
+
+    #[test]
+    fn script_path() {
+        assert_eq!(
+            variable(&b"$0"[..]),
+            IResult::Done(&b""[..], WordPart::Argument(0))
+        );
+    }
+
+    #[test]
+    fn bare_argument_is_one_digit() {
+        assert_eq!(
+            variable(&b"$10"[..]),
+            IResult::Done(&b"0"[..], WordPart::Argument(1))
+        );
+    }
+
+    #[test]
+    fn braced_argument() {
+        assert_eq!(
+            variable(&b"${10}"[..]),
+            IResult::Done(&b""[..], WordPart::Argument(10))
+        );
+    }
+
+    #[test]
+    fn all_arguments() {
+        assert_eq!(
+            variable(&b"$@"[..]),
+            IResult::Done(&b""[..], WordPart::Arguments)
+        );
+    }
+
+    #[test]
+    fn argument_count() {
+        assert_eq!(
+            variable(&b"$#"[..]),
+            IResult::Done(&b""[..], WordPart::ArgumentCount)
+        );
+    }
+
+    #[test]
+    fn lone_dollar() {
+        assert!(variable(&b"$"[..]).is_err());
+    }
+}
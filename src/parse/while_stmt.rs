@@ -1,13 +1,23 @@
+use super::loop_control::label;
 use super::*;
 use crate::ast::*;
 use nom::*;
 
 named!(pub while_stmt<While>, do_parse!(
-        tag!("while") >>
-        command: ws!(command) >>
-        block: ast >>
-        (While::new(command, block))
-      ));
+    start: here >>
+    label: label >>
+    call!(keyword, "while") >>
+    predicate: expect!("expected a condition after `while`", ws!(predicate)) >>
+    block: expect!("expected a statement after the condition", ast) >>
+    end: here >>
+    ({
+        let stmt = While::new(predicate, block).at(span(start, end));
+        match label {
+            Some(label) => stmt.labelled(label),
+            None => stmt,
+        }
+    })
+));
 
 #[cfg(test)]
 mod tests {
@@ -19,7 +29,19 @@ mod tests {
             while_stmt(&b"while true {}"[..]),
             IResult::Done(
                 &b""[..],
-                While::new(Command::new("true", vec![]), AST::Block(Block(vec![])))
+                While::new(Command::new("true", vec![]), AST::Block(Block::new(vec![])))
+            )
+        );
+    }
+
+    #[test]
+    fn labelled() {
+        assert_eq!(
+            while_stmt(&b"outer: while true {}"[..]),
+            IResult::Done(
+                &b""[..],
+                While::new(Command::new("true", vec![]), AST::Block(Block::new(vec![])))
+                    .labelled("outer")
             )
         );
     }
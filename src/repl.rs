@@ -0,0 +1,98 @@
+mod editor;
+mod history;
+
+use std::path::PathBuf;
+
+use crate::error::Error;
+
+use self::editor::{Editor, Input};
+use self::history::History;
+use crate::env::{set_interrupted, Env, Unwind};
+use crate::executor::{Executor, Success};
+use crate::parse::*;
+
+const PROMPT: &str = "cinnamon> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// Reads statements from the terminal and runs them until end of input or `exit`, returning the
+/// code the shell should exit with.
+pub fn run<E: Executor>(executor: &mut E, env: &mut Env) -> Result<i32, Error> {
+    catch_interrupts();
+    let mut editor = Editor::new(History::load(history_file()));
+
+    while let Some(source) = read_statements(&mut editor)? {
+        let ast = match source.into_bytes().parse() {
+            Ok(ast) => ast,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+
+        set_interrupted(false);
+        for statement in ast {
+            match statement.execute_with(executor, env) {
+                Ok(status) => {
+                    if env.catch(Unwind::Exit) {
+                        return Ok(status.map(|s| s.exit_code()).unwrap_or(0));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    env.catch(Unwind::Interrupt);
+                    break;
+                }
+            }
+            env.check_interrupt();
+            if env.catch(Unwind::Interrupt) {
+                // The terminal echoed ^C without a newline.
+                println!();
+                break;
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Reads lines until they make up whole statements, or `None` at the end of input.
+fn read_statements(editor: &mut Editor) -> Result<Option<String>, Error> {
+    let mut source = String::new();
+    loop {
+        let prompt = if source.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match editor.read_line(prompt)? {
+            Input::Line(line) => {
+                source.push_str(&line);
+                source.push('\n');
+            }
+            Input::Interrupted => {
+                source.clear();
+                continue;
+            }
+            Input::Eof => return Ok(None),
+        }
+        if !is_unfinished(source.as_bytes()) {
+            return Ok(Some(source));
+        }
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cinnamon_history"))
+}
+
+extern "C" fn interrupted(_signal: libc::c_int) {
+    set_interrupted(true);
+}
+
+/// Makes Ctrl-C stop the running command without stopping the shell. Commands still get the
+/// default behaviour, since a caught signal is reset when a process execs.
+fn catch_interrupts() {
+    let handler: extern "C" fn(libc::c_int) = interrupted;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
@@ -0,0 +1,495 @@
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+
+use crate::error::Error;
+
+use super::history::History;
+
+/// What the user did at the prompt.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /// Ctrl-C, abandoning the line.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of a non-terminal stdin.
+    Eof,
+}
+
+/// Reads lines from the terminal with cursor movement and history recall.
+pub struct Editor {
+    history: History,
+}
+
+impl Editor {
+    pub fn new(history: History) -> Editor {
+        Editor { history }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> Result<Input, Error> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return read_plain_line();
+        }
+
+        let raw = RawMode::enable()?;
+        let mut stdin = Stdin;
+        let mut line = Line::default();
+        // Which history entry is shown, with `history.len()` meaning the line being written.
+        let mut recalled = self.history.len();
+        let mut draft = String::new();
+
+        render(prompt, &line)?;
+        loop {
+            let key = match Key::read(&mut stdin)? {
+                Some(key) => key,
+                None => return Ok(Input::Eof),
+            };
+            match key {
+                Key::Enter => {
+                    print!("\r\n");
+                    let text = line.to_string();
+                    drop(raw);
+                    self.history.push(&text);
+                    return Ok(Input::Line(text));
+                }
+                Key::Interrupt => {
+                    print!("^C\r\n");
+                    return Ok(Input::Interrupted);
+                }
+                Key::Eof if line.is_empty() => {
+                    print!("\r\n");
+                    return Ok(Input::Eof);
+                }
+                Key::Eof | Key::Delete => line.delete(),
+                Key::Backspace => line.backspace(),
+                Key::Char(c) => line.insert(c),
+                Key::Left => line.left(),
+                Key::Right => line.right(),
+                Key::Home => line.home(),
+                Key::End => line.end(),
+                Key::KillToStart => line.kill_to_start(),
+                Key::KillToEnd => line.kill_to_end(),
+                Key::Up if recalled > 0 => {
+                    if recalled == self.history.len() {
+                        draft = line.to_string();
+                    }
+                    recalled -= 1;
+                    line = Line::from(self.history.get(recalled).unwrap_or_default());
+                }
+                Key::Down if recalled < self.history.len() => {
+                    recalled += 1;
+                    line = Line::from(self.history.get(recalled).unwrap_or(&draft));
+                }
+                Key::Up | Key::Down | Key::Other => {}
+            }
+            render(prompt, &line)?;
+        }
+    }
+}
+
+fn read_plain_line() -> Result<Input, Error> {
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(Input::Eof);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+    }
+    Ok(Input::Line(line))
+}
+
+fn render(prompt: &str, line: &Line) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    // Return to the start of the row, redraw, clear whatever is left of the old line, then move
+    // back to the cursor.
+    write!(stdout, "\r{}{}\x1b[K", prompt, line)?;
+    let after_cursor = line.chars.len() - line.cursor;
+    if after_cursor > 0 {
+        write!(stdout, "\x1b[{}D", after_cursor)?;
+    }
+    stdout.flush()
+}
+
+/// Puts the terminal in raw mode until dropped, so keys arrive one at a time without being echoed
+/// and Ctrl-C is read as a key rather than sent as a signal.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        unsafe {
+            let mut original: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode(original))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.0);
+        }
+    }
+}
+
+/// Unbuffered stdin, so nothing typed ahead is held back from the commands we run.
+struct Stdin;
+
+impl Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToStart,
+    KillToEnd,
+    Interrupt,
+    Eof,
+    Other,
+}
+
+impl Key {
+    /// Reads the next key press, or `None` at the end of input.
+    fn read<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+        let byte = match read_byte(input)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            0x01 => Key::Home,
+            0x05 => Key::End,
+            0x02 => Key::Left,
+            0x06 => Key::Right,
+            0x10 => Key::Up,
+            0x0e => Key::Down,
+            0x15 => Key::KillToStart,
+            0x0b => Key::KillToEnd,
+            0x03 => Key::Interrupt,
+            0x04 => Key::Eof,
+            0x1b => read_escape(input)?,
+            b if b < 0x20 => Key::Other,
+            b => read_char(input, b)?,
+        };
+        Ok(Some(key))
+    }
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the rest of an escape sequence like `\x1b[A`, after the `\x1b`.
+fn read_escape<R: Read>(input: &mut R) -> io::Result<Key> {
+    let introducer = read_byte(input)?;
+    if introducer != Some(b'[') && introducer != Some(b'O') {
+        return Ok(Key::Other);
+    }
+    let mut params = Vec::new();
+    loop {
+        match read_byte(input)? {
+            None => return Ok(Key::Other),
+            Some(b) if (0x40..=0x7e).contains(&b) => {
+                return Ok(match (&params[..], b) {
+                    (_, b'A') => Key::Up,
+                    (_, b'B') => Key::Down,
+                    (_, b'C') => Key::Right,
+                    (_, b'D') => Key::Left,
+                    (_, b'H') | (b"1", b'~') | (b"7", b'~') => Key::Home,
+                    (_, b'F') | (b"4", b'~') | (b"8", b'~') => Key::End,
+                    (b"3", b'~') => Key::Delete,
+                    _ => Key::Other,
+                });
+            }
+            Some(b) => params.push(b),
+        }
+    }
+}
+
+/// Reads the rest of a UTF-8 encoded character starting with `first`.
+fn read_char<R: Read>(input: &mut R, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(b) => bytes.push(b),
+            None => return Ok(Key::Other),
+        }
+    }
+    Ok(match std::str::from_utf8(&bytes) {
+        Ok(s) => s.chars().next().map(Key::Char).unwrap_or(Key::Other),
+        Err(_) => Key::Other,
+    })
+}
+
+/// The text being edited and where the cursor is within it.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    fn kill_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    fn kill_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+}
+
+impl<'a> From<&'a str> for Line {
+    /// A line holding `s` with the cursor at the end.
+    fn from(s: &'a str) -> Line {
+        let chars: Vec<char> = s.chars().collect();
+        Line {
+            cursor: chars.len(),
+            chars,
+        }
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.chars.iter().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(test)]
+    mod line {
+        use super::*;
+
+        #[test]
+        fn insert_at_cursor() {
+            let mut line = Line::from("echo;");
+
+            line.left();
+            line.insert(' ');
+            line.insert('a');
+
+            assert_eq!(line.to_string(), "echo a;");
+            assert_eq!(line.cursor, 6);
+        }
+
+        #[test]
+        fn backspace_at_start_does_nothing() {
+            let mut line = Line::from("ls");
+
+            line.home();
+            line.backspace();
+
+            assert_eq!(line.to_string(), "ls");
+        }
+
+        #[test]
+        fn delete_under_cursor() {
+            let mut line = Line::from("lls");
+
+            line.home();
+            line.delete();
+
+            assert_eq!(line.to_string(), "ls");
+            assert_eq!(line.cursor, 0);
+        }
+
+        #[test]
+        fn cursor_stays_in_bounds() {
+            let mut line = Line::from("ls");
+
+            line.right();
+            line.home();
+            line.left();
+
+            assert_eq!(line.cursor, 0);
+        }
+
+        #[test]
+        fn kill_to_start() {
+            let mut line = Line::from("echo foo");
+
+            line.left();
+            line.left();
+            line.left();
+            line.kill_to_start();
+
+            assert_eq!(line.to_string(), "foo");
+            assert_eq!(line.cursor, 0);
+        }
+
+        #[test]
+        fn kill_to_end() {
+            let mut line = Line::from("echo foo");
+
+            line.home();
+            line.right();
+            line.right();
+            line.right();
+            line.right();
+            line.kill_to_end();
+
+            assert_eq!(line.to_string(), "echo");
+        }
+
+        #[test]
+        fn multi_byte_characters() {
+            let mut line = Line::from("héllo");
+
+            line.left();
+            line.left();
+            line.left();
+            line.backspace();
+
+            assert_eq!(line.to_string(), "hllo");
+        }
+    }
+
+    #[cfg(test)]
+    mod key {
+        use super::*;
+
+        fn keys(input: &[u8]) -> Vec<Key> {
+            let mut input = input;
+            std::iter::from_fn(|| Key::read(&mut input).unwrap()).collect()
+        }
+
+        #[test]
+        fn characters() {
+            assert_eq!(keys(b"ls"), vec![Key::Char('l'), Key::Char('s')]);
+        }
+
+        #[test]
+        fn utf8() {
+            assert_eq!(keys("é".as_bytes()), vec![Key::Char('é')]);
+        }
+
+        #[test]
+        fn enter() {
+            assert_eq!(keys(b"\r\n"), vec![Key::Enter, Key::Enter]);
+        }
+
+        #[test]
+        fn control_keys() {
+            assert_eq!(
+                keys(b"\x03\x04\x7f\x01\x05"),
+                vec![
+                    Key::Interrupt,
+                    Key::Eof,
+                    Key::Backspace,
+                    Key::Home,
+                    Key::End
+                ]
+            );
+        }
+
+        #[test]
+        fn arrows() {
+            assert_eq!(
+                keys(b"\x1b[A\x1b[B\x1b[C\x1b[D"),
+                vec![Key::Up, Key::Down, Key::Right, Key::Left]
+            );
+        }
+
+        #[test]
+        fn delete_and_home_sequences() {
+            assert_eq!(
+                keys(b"\x1b[3~\x1b[1~\x1bOF"),
+                vec![Key::Delete, Key::Home, Key::End]
+            );
+        }
+
+        #[test]
+        fn unknown_sequence() {
+            assert_eq!(keys(b"\x1b[15~x"), vec![Key::Other, Key::Char('x')]);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::error::Error;
+
+/// Lines entered at the prompt, oldest first, kept in a file so they survive between sessions.
+/// Saving is best-effort: if the file can't be read or written, that's said once and history
+/// only lasts this session.
+pub struct History {
+    entries: Vec<String>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// Reads the history saved in `file`, if any, skipping lines that aren't UTF-8. Without a
+    /// file, history only lasts this session.
+    pub fn load(file: Option<PathBuf>) -> History {
+        let mut history = History {
+            entries: Vec::new(),
+            file,
+        };
+        let contents = match history.file.as_ref().map(fs::read) {
+            Some(Ok(contents)) => contents,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                history.stop_saving(e);
+                return history;
+            }
+            _ => return history,
+        };
+        history.entries = contents
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| String::from_utf8(line.to_vec()).ok())
+            .collect();
+        history
+    }
+
+    /// Records `line`, unless it is blank or repeats the previous entry.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().map(AsRef::as_ref) == Some(line) {
+            return;
+        }
+        self.entries.push(line.to_owned());
+        if let Some(path) = &self.file {
+            let saved = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            if let Err(e) = saved {
+                self.stop_saving(e);
+            }
+        }
+    }
+
+    /// Warns that the history file failed with `source` and keeps history in memory from now on.
+    fn stop_saving(&mut self, source: io::Error) {
+        if let Some(path) = self.file.take() {
+            eprintln!(
+                "warning: history won't be saved: {}",
+                Error::Open { path, source }
+            );
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(AsRef::as_ref)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("cinnamon-history-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn push_then_get() {
+        let mut history = History::load(None);
+
+        history.push("echo foo;");
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.get(0), Some("echo foo;"));
+    }
+
+    #[test]
+    fn skips_blank_lines() {
+        let mut history = History::load(None);
+
+        history.push("   ");
+
+        assert_eq!(history.len(), 0);
+    }
+
+    #[test]
+    fn skips_repeats() {
+        let mut history = History::load(None);
+
+        history.push("ls;");
+        history.push("ls;");
+
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let history = History::load(Some(temp_file("missing")));
+
+        assert_eq!(history.len(), 0);
+    }
+
+    #[test]
+    fn persists_between_loads() {
+        let path = temp_file("persists");
+
+        let mut history = History::load(Some(path.clone()));
+        history.push("echo foo;");
+        history.push("echo bar;");
+        let history = History::load(Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(history.get(0), Some("echo foo;"));
+        assert_eq!(history.get(1), Some("echo bar;"));
+    }
+
+    #[test]
+    fn unwritable_file_keeps_session_history() {
+        let mut history = History::load(Some(PathBuf::from("/nonexistent/history")));
+
+        history.push("echo foo;");
+        history.push("echo bar;");
+
+        assert_eq!(history.len(), 2);
+        assert!(history.file.is_none());
+    }
+
+    #[test]
+    fn skips_non_utf8_lines() {
+        let path = temp_file("non-utf8");
+        fs::write(&path, b"echo foo;\necho caf\xe9;\necho bar;\n").unwrap();
+
+        let history = History::load(Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.get(1), Some("echo bar;"));
+    }
+}
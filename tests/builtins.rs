@@ -0,0 +1,55 @@
+use cinnamon::{Context, Error, Interpreter, Status, Success};
+
+fn greet(args: &[String], context: &mut Context) -> Result<Status, Error> {
+    writeln!(context.stdout, "hello {}", args.join(" "))?;
+    Ok(Status::from_code(0))
+}
+
+#[test]
+fn defined_builtin_runs_in_scripts() {
+    let mut interpreter = Interpreter::new();
+
+    interpreter.define_builtin("greet", greet);
+    interpreter.eval("let greeting = $(greet world)\n").unwrap();
+
+    assert_eq!(interpreter.variable("greeting"), Some("hello world"));
+}
+
+#[test]
+fn status_and_state_reach_the_script() {
+    let mut interpreter = Interpreter::new();
+
+    interpreter.define_builtin("remember", |args, context| {
+        context.set_variable("remembered", args.join(" "));
+        Ok(Status::from_code(args.len() as i32))
+    });
+    let code = interpreter.eval("remember a b\n").unwrap();
+
+    assert_eq!(code, 2);
+    assert_eq!(interpreter.variable("remembered"), Some("a b"));
+}
+
+#[test]
+fn builtin_sees_variables_and_directory() {
+    let mut interpreter = Interpreter::new();
+
+    interpreter.define_builtin("where", |_, context| {
+        let place = format!(
+            "{} in {}",
+            context.variable("who").unwrap_or("nobody"),
+            context.current_dir().display()
+        );
+        context.export("CINNAMON_PLACE", place);
+        Ok(Status::from_code(0))
+    });
+    interpreter
+        .eval(
+            "let who = me
+where
+",
+        )
+        .unwrap();
+
+    let expected = format!("me in {}", std::env::current_dir().unwrap().display());
+    assert_eq!(interpreter.variable("CINNAMON_PLACE"), Some(&expected[..]));
+}
@@ -0,0 +1,216 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn cinnamon(args: &[OsString]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cinnamon"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs `program` with `-c` from `dir`.
+fn run(program: &str, dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cinnamon"))
+        .args(["-c", program])
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// A fresh directory for one test to write files in.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cinnamon-cli-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn non_utf8_script_name() {
+    let dir = scratch("non-utf8");
+    let script = dir.join(OsString::from_vec(b"caf\xe9.cm".to_vec()));
+    fs::write(&script, "exit 3\n").unwrap();
+
+    let output = cinnamon(&[script.into_os_string()]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stderr, b"");
+}
+
+#[test]
+fn non_utf8_name_after_inline_program() {
+    let name = OsString::from_vec(b"caf\xe9".to_vec());
+
+    let output = cinnamon(&["-c".into(), "exit 4".into(), name]);
+
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(output.stderr, b"");
+}
+
+#[test]
+fn non_utf8_environment_reaches_commands() {
+    let value = OsString::from_vec(b"caf\xe9".to_vec());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cinnamon"))
+        .args(["-c", "printenv CINNAMON_TEST"])
+        .env("CINNAMON_TEST", &value)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"caf\xe9\n");
+}
+
+#[test]
+fn relative_command_in_non_utf8_directory() {
+    let dir = scratch("non-utf8-dir").join(OsString::from_vec(b"caf\xe9".to_vec()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("tool"), "#!/bin/sh\necho ran\n").unwrap();
+    fs::set_permissions(dir.join("tool"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = run("./tool", &dir);
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ran\n");
+}
+
+#[test]
+fn builtin_in_pipeline() {
+    let dir = scratch("builtin-pipeline").canonicalize().unwrap();
+
+    let output = run("pwd | cat", &dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{}\n", dir.display()).into_bytes());
+}
+
+#[test]
+fn builtin_in_pipeline_leaves_shell_alone() {
+    let dir = scratch("builtin-pipeline-cd").canonicalize().unwrap();
+
+    let output = run("cd / | true; pwd", &dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{}\n", dir.display()).into_bytes());
+}
+
+#[test]
+fn function_output_redirected() {
+    let dir = scratch("function-redirect");
+
+    let output = run("fn f() { echo hi; pwd; }\nf > out.txt; echo after", &dir);
+    let contents = fs::read_to_string(dir.join("out.txt")).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"after\n");
+    assert_eq!(contents, format!("hi\n{}\n", dir.display()));
+}
+
+#[test]
+fn function_in_pipeline() {
+    let dir = scratch("function-pipeline");
+
+    let output = run("fn f() { echo hi; echo there; }\nf | wc -l", &dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "2");
+}
+
+#[test]
+fn function_output_captured() {
+    let dir = scratch("function-capture");
+
+    let output = run("fn f(x) { echo got $x; }\necho [$(f 1)]", &dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"[got 1]\n");
+}
+
+#[test]
+fn warning_does_not_blame_earlier_command() {
+    let dir = scratch("warn-blame");
+
+    let output = run(
+        "fn f() { return 1; }\n@warn\necho before\nf\necho after\n",
+        &dir,
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "-c:4:1: warning: f failed with status 1\n"
+    );
+}
+
+#[test]
+fn unwritable_trace_file_is_named() {
+    let output = cinnamon(&[
+        "--trace-file".into(),
+        "/nonexistent/trace.log".into(),
+        "-c".into(),
+        "true".into(),
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("cannot open /nonexistent/trace.log: "));
+}
+
+#[test]
+fn interrupt_stops_loop_at_prompt() {
+    let (mut master, slave) = unsafe {
+        let (mut master, mut slave) = (0, 0);
+        let opened = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+        assert_eq!(opened, 0);
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_cinnamon"))
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .unwrap();
+
+    master
+        .write_all(b"while true { pwd > /dev/null }\r")
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    unsafe { libc::kill(shell.id() as libc::pid_t, libc::SIGINT) };
+    master.write_all(b"exit 7\r").unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = shell.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            shell.kill().unwrap();
+            panic!("the loop kept running after ^C");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(7));
+}